@@ -0,0 +1,254 @@
+use std::{fs, cmp::Reverse, error::Error, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{KeynoteFile, is_secret_value, error::{invalid_input, not_found}, permissions::create_private_dir, recovery::lock_writes};
+
+/// name of the folder, next to the data file, that backups are kept in
+pub const BACKUP_FOLDER_NAME: &str = "backups";
+
+/// Controls how many previous versions of a data file are kept before each rewrite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupPolicy {
+    /// maximum number of backups to keep, the oldest are removed first
+    pub keep: usize,
+    /// backups older than this are removed. None keeps backups regardless of age
+    pub max_age: Option<Duration>
+}
+
+impl Default for BackupPolicy {
+    fn default() -> BackupPolicy {
+        BackupPolicy { keep: 10, max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)) }
+    }
+}
+
+/// A saved previous version of a data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// path to the backup file
+    pub path: PathBuf,
+    /// when the backup was taken
+    pub created: SystemTime
+}
+
+impl KeynoteFile {
+    /// Sets the backup policy. When set, the previous version of the file is saved in the backups folder
+    /// before each rewrite by `add_entry`, `remove_entry` and `remove_section`. None turns backups off
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - BackupPolicy to apply, or None
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_backup_doc.dat").unwrap();
+    /// kn_file.set_backup_policy(Some(BackupPolicy { keep: 2, max_age: None }));
+    /// kn_file.add_section("leaders").unwrap();
+    ///
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+    /// kn_file.add_entry("leaders", "corrino", "shaddam").unwrap();
+    ///
+    /// let backups = kn_file.list_backups().unwrap();
+    /// assert_eq!(backups.len(), 2);
+    ///
+    /// for backup in backups {
+    ///     fs::remove_file(backup.path);       // remove the backups
+    /// }
+    /// fs::remove_file(kn_file.filepath);      // remove the test file
+    /// ```
+    pub fn set_backup_policy(&mut self, policy: Option<BackupPolicy>) {
+        self.backup_policy = policy;
+    }
+
+    /// Returns the backups of this file, newest first
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::*;
+    ///
+    /// let kn_file = KeynoteFile::new("kntest_list_backups_doc.dat").unwrap();
+    /// assert!(kn_file.list_backups().unwrap().is_empty());
+    /// ```
+    pub fn list_backups(&self) -> Result<Vec<Backup>, Box<dyn Error>> {
+        find_backups(&self.filepath)
+    }
+
+    /// Replaces the data file with one of its backups and reloads it. The current version is backed up first
+    /// when a backup policy is set
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - which backup to restore, 1 is the newest as returned by `list_backups`
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_restore_doc.dat").unwrap();
+    /// kn_file.set_backup_policy(Some(BackupPolicy::default()));
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.remove_entry("atreides").unwrap();
+    ///
+    /// kn_file.set_backup_policy(None);
+    /// kn_file.restore_backup(1).unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+    ///
+    /// for backup in kn_file.list_backups().unwrap() {
+    ///     fs::remove_file(backup.path);       // remove the backups
+    /// }
+    /// fs::remove_file(kn_file.filepath);      // remove the test file
+    /// ```
+    pub fn restore_backup(&mut self, n: usize) -> Result<Backup, Box<dyn Error>> {
+        let backups = self.list_backups()?;
+        let backup = match n.checked_sub(1).and_then(|i| backups.get(i)) {
+            Some(backup) => backup.clone(),
+            None => return Err(not_found(format!("backup {} does not exist. {} backup(s) available", n, backups.len())))
+        };
+
+        {
+            let _lock = lock_writes(&self.filepath)?;
+            let (tmp_filepath, tmp_file) = self.create_temp_file()?;
+            fs::copy(&backup.path, &tmp_filepath)?;
+            self.replace_with_temp_file(&tmp_filepath, tmp_file)?;
+        }
+
+        self.load_data()?;
+        Ok(backup)
+    }
+
+    // seals the plain text values the backups hold for the keys, rewriting the backups that held them
+    pub(crate) fn seal_backed_up_values(&self, keys: &[String]) -> Result<(), Box<dyn Error>> {
+        for backup in self.list_backups()? {
+            let contents = self.read_text_file(&backup.path)?;
+
+            let mut sealed = String::new();
+            for line in contents.split_inclusive('\n') {
+                match KeynoteFile::get_entry_from_string(line.trim_end_matches('\n')) {
+                    Some((key, value)) if keys.iter().any(|k| k == key) && !is_secret_value(value) =>
+                        sealed.push_str(&KeynoteFile::build_entry_string(key, &self.seal_secret(key, value)?)),
+                    _ => sealed.push_str(line)
+                }
+            }
+
+            if sealed != contents {
+                self.write_text_file(&backup.path, &sealed)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies the data file into the backups folder, then removes backups the policy no longer keeps
+pub(crate) fn create_backup(filepath: &Path, policy: &BackupPolicy) -> Result<(), Box<dyn Error>> {
+    if !filepath.exists() {
+        return Ok(());
+    }
+
+    let folder = backup_folder(filepath);
+    if !folder.exists() {
+        create_private_dir(&folder)?;
+    }
+
+    let filename = get_filename(filepath)?;
+    let mut millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let mut backup_path = folder.join(format!("{}.{}.bak", filename, millis));
+    while backup_path.exists() {        // two writes in the same millisecond
+        millis += 1;
+        backup_path = folder.join(format!("{}.{}.bak", filename, millis));
+    }
+    fs::copy(filepath, &backup_path)?;
+
+    prune_backups(filepath, policy)
+}
+
+// backups of the data file at filepath, newest first
+fn find_backups(filepath: &Path) -> Result<Vec<Backup>, Box<dyn Error>> {
+    let folder = backup_folder(filepath);
+    if !folder.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}.", get_filename(filepath)?);
+    let mut backups = Vec::new();
+    for dir_entry in fs::read_dir(folder)? {
+        let path = dir_entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue
+        };
+
+        // backups are named <filename>.<milliseconds since epoch>.bak
+        let millis = name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(".bak")).and_then(|ms| ms.parse::<u64>().ok());
+        if let Some(millis) = millis {
+            backups.push(Backup { path, created: UNIX_EPOCH + Duration::from_millis(millis) });
+        }
+    }
+
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+// removes backups beyond the policy's count, and those older than its max age
+fn prune_backups(filepath: &Path, policy: &BackupPolicy) -> Result<(), Box<dyn Error>> {
+    let now = SystemTime::now();
+    for (i, backup) in find_backups(filepath)?.iter().enumerate() {
+        let too_old = match policy.max_age {
+            Some(max_age) => now.duration_since(backup.created).map(|age| age > max_age).unwrap_or(false),
+            None => false
+        };
+
+        if i >= policy.keep || too_old {
+            fs::remove_file(&backup.path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn backup_folder(filepath: &Path) -> PathBuf {
+    filepath.with_file_name(BACKUP_FOLDER_NAME)
+}
+
+fn get_filename(filepath: &Path) -> Result<String, Box<dyn Error>> {
+    match filepath.file_name() {
+        Some(name) => Ok(name.to_string_lossy().to_string()),
+        None => Err(invalid_input("error: invalid data file path"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_backup_prunes_to_keep_count() {
+        let mut kn_file = KeynoteFile::new("kntest_backup_prune.dat").unwrap();
+        kn_file.add_section("leaders").unwrap();
+        let policy = BackupPolicy { keep: 3, max_age: None };
+
+        for _ in 0..5 {
+            create_backup(&kn_file.filepath, &policy).unwrap();
+        }
+
+        let backups = kn_file.list_backups().unwrap();
+        assert_eq!(backups.len(), 3);
+        assert!(backups[0].created > backups[2].created);
+        assert!(fs::read_to_string(&backups[0].path).unwrap().starts_with("<leaders>\n"));
+
+        prune_backups(&kn_file.filepath, &BackupPolicy { keep: 0, max_age: None }).unwrap();
+        assert!(kn_file.list_backups().unwrap().is_empty());
+
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn restore_backup_out_of_range_is_error() {
+        let mut kn_file = KeynoteFile::new("kntest_backup_range.dat").unwrap();
+        assert!(kn_file.restore_backup(0).is_err());
+        assert!(kn_file.restore_backup(1).is_err());
+    }
+}
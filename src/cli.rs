@@ -0,0 +1,403 @@
+//! Command line interface of kn. Commands are grouped by what they act on, e.g. `kn section add work`.
+//! The single dash options of earlier versions, e.g. `kn -as work`, are still accepted as aliases
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::complete::CompletionShell;
+use crate::export::ExportShell;
+use crate::output::{Format, Output};
+
+// kept in step with exit_code in main.rs
+const EXIT_CODES_HELP: &str = "exit codes:
+  0  success
+  1  failure of another kind
+  2  invalid input, e.g. a bad section name or wrong passphrase
+  3  not found, e.g. a missing section, key or notebook
+  4  already exists
+  5  corrupt data, journal or history file
+  6  I/O error
+
+options of earlier versions, e.g. kn -as [section] or kn -lv [key], still work";
+
+#[derive(Debug, Parser)]
+#[command(name = "kn", version, arg_required_else_help = true,
+          about = "keeps notes as key-value pairs organized into named sections",
+          after_help = EXIT_CODES_HELP)]
+pub struct Cli {
+    /// work on NOTEBOOK instead of the default one
+    #[arg(short = 'n', long, global = true, value_name = "NOTEBOOK")]
+    pub notebook: Option<String>,
+
+    /// write results, and errors, as json. the same as --format json
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// how results, and errors, are written
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text, value_name = "FORMAT")]
+    pub format: Format,
+
+    /// show what the command would change, without changing anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Command
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// add, remove and list sections
+    #[command(subcommand)]
+    Section(SectionCommand),
+    /// add, change, remove and list entries
+    #[command(subcommand)]
+    Entry(EntryCommand),
+    /// tag entries and list them by tag
+    #[command(subcommand)]
+    Tag(TagCommand),
+    /// list, create, delete and pick notebooks
+    Notebook {
+        #[command(subcommand)]
+        action: Option<NotebookCommand>
+    },
+    /// list entries in every notebook whose key or value holds TEXT, ignoring case
+    Find {
+        text: String
+    },
+    /// list and restore saved previous versions of the notebook
+    #[command(subcommand)]
+    Backup(BackupCommand),
+    /// encrypt the notebook with a passphrase
+    ///
+    /// the passphrase is asked for on each later use. set KEYNOTES_PASSPHRASE to avoid the prompt
+    Encrypt,
+    /// store the notebook as plain text again
+    Decrypt,
+    /// report problems in the notebook's data file
+    Fsck {
+        /// repair the problems, keeping a backup of the original
+        #[arg(long)]
+        fix: bool
+    },
+    /// list the changes made to the notebook, oldest first
+    History,
+    /// reverse the last N changes made to the notebook
+    Undo {
+        #[arg(default_value_t = 1)]
+        n: usize
+    },
+    /// change the value of KEY, or the entries of SECTION, in $VISUAL or $EDITOR
+    ///
+    /// a section is shown one entry per line as key = value. changes are checked, then written in
+    /// one step, so nothing changes if any line is invalid. the editor is given a plain text temp file,
+    /// so secret values and encrypted notebooks are only edited with --reveal
+    Edit {
+        #[arg(value_name = "KEY|SECTION")]
+        name: String,
+        /// edit the section NAME even if an entry has that key
+        #[arg(short, long)]
+        section: bool,
+        /// edit a secret value, or an encrypted notebook, even though the temp file holds it as plain text
+        #[arg(long)]
+        reveal: bool
+    },
+    /// write the entries of SECTION, or of every section, as environment variables for a shell to evaluate
+    ///
+    /// keys must be valid variable names. references to other entries are expanded and secret values
+    /// revealed. e.g. eval "$(kn env work)", or kn env work --shell fish | source
+    Env {
+        section: Option<String>,
+        /// syntax of the lines written
+        #[arg(long, value_enum, default_value_t = ExportShell::Sh)]
+        shell: ExportShell
+    },
+    /// run COMMAND with the entries of SECTION, or of every section, added to its environment
+    ///
+    /// keys must be valid variable names. e.g. kn exec work -- cargo run
+    Exec {
+        section: Option<String>,
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>
+    },
+    /// read commands in a loop, loading the notebook once
+    ///
+    /// commands are add, get, rm, ls, find and use. use picks a section for entries to be added to.
+    /// keys and sections complete with tab
+    #[command(short_flag = 'i', long_flag = "interactive")]
+    Repl,
+    /// browse and edit the notebook in a full-screen interface
+    ///
+    /// sections are listed on the left and the entries of the selected section on the right.
+    /// press / to search, a to add, e to edit, r to rename, d to delete and q to quit
+    Tui,
+    /// write a completion script for SHELL
+    ///
+    /// section names, keys, tags and notebooks are completed from the notebook in use.
+    /// e.g. add `source <(kn completions bash)` to ~/.bashrc
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell
+    },
+    /// list the names that can complete the next word after WORDS. used by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SectionCommand {
+    /// add a section
+    ///
+    /// names may contain letters, digits, '-', '_' and '.'.
+    /// nest sections with '/', e.g. work/clientA. missing parent sections are added
+    Add {
+        name: String
+    },
+    /// delete a section, and any nested in it
+    ///
+    /// asks first if the section holds entries or nested sections
+    Remove {
+        name: String,
+        /// delete without asking
+        #[arg(short, long)]
+        yes: bool
+    },
+    /// list the sections as a tree, or those nested in SECTION
+    List {
+        section: Option<String>
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum EntryCommand {
+    /// add an entry to SECTION
+    ///
+    /// keys follow the same naming rules as section names. the words of VALUE are joined with spaces,
+    /// so it needs no quotes. put -- before a VALUE starting with -
+    Add {
+        section: String,
+        key: String,
+        #[arg(required = true, allow_negative_numbers = true)]
+        value: Vec<String>,
+        /// encrypt the value with a passphrase
+        #[arg(short, long)]
+        secret: bool
+    },
+    /// replace the value of an entry
+    Update {
+        key: String,
+        #[arg(required = true, allow_negative_numbers = true)]
+        value: Vec<String>
+    },
+    /// remove an entry
+    Remove {
+        key: String
+    },
+    /// show the value of an entry. secret values show as ****
+    Get {
+        key: String,
+        /// show a secret value. piped, only the value is written
+        #[arg(short, long)]
+        reveal: bool,
+        /// expand ${section.key} references to other entries
+        #[arg(long)]
+        resolve: bool
+    },
+    /// list the keys in the notebook
+    List {
+        /// list only keys changed within AGE, e.g. 30m, 12h, 7d
+        #[arg(long, value_name = "AGE")]
+        since: Option<String>,
+        /// list the most recently changed first
+        #[arg(long, value_enum)]
+        sort: Option<SortOrder>
+    },
+    /// encrypt the value of an entry with a passphrase
+    Secret {
+        key: String
+    },
+    /// store the value of a secret entry as plain text again
+    Unsecret {
+        key: String
+    },
+    /// list keys with prior values, or the prior values of KEY
+    ///
+    /// with N, restores value N from the list
+    History {
+        key: Option<String>,
+        n: Option<usize>
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// most recently changed first
+    Age
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TagCommand {
+    /// give an entry a tag
+    Add {
+        key: String,
+        tag: String
+    },
+    /// remove a tag from an entry
+    Remove {
+        key: String,
+        tag: String
+    },
+    /// list entries with all of TAGS, or every tag in use. '!tag' excludes a tag
+    List {
+        tags: Vec<String>,
+        /// list entries with any of TAGS
+        #[arg(long)]
+        any: bool
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NotebookCommand {
+    /// list the notebooks, marking the one used by default with *
+    List,
+    /// add a notebook
+    Create {
+        name: String
+    },
+    /// make NAME the notebook used by default
+    Use {
+        name: String
+    },
+    /// delete a notebook with its journal, history and backups
+    Delete {
+        name: String
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupCommand {
+    /// list the saved previous versions of the notebook, newest first
+    List,
+    /// replace the notebook with backup N from the list
+    Restore {
+        n: usize
+    }
+}
+
+impl Command {
+    /// Checks if the command changes a notebook, or which notebooks there are
+    pub fn changes_notebook(&self) -> bool {
+        match self {
+            Command::Section(SectionCommand::List { .. }) => false,
+            Command::Entry(EntryCommand::Get { .. } | EntryCommand::List { .. }) => false,
+            Command::Entry(EntryCommand::History { n, .. }) => n.is_some(),
+            Command::Tag(TagCommand::List { .. }) => false,
+            Command::Backup(BackupCommand::List) => false,
+            Command::Notebook { action } => !matches!(action, None | Some(NotebookCommand::List)),
+            Command::Fsck { fix } => *fix,
+            Command::Find { .. } | Command::History | Command::Completions { .. } | Command::Complete { .. } => false,
+            Command::Env { .. } | Command::Exec { .. } => false,
+            _ => true
+        }
+    }
+}
+
+impl Cli {
+    /// Returns the format results are written in
+    pub fn output(&self) -> Output {
+        Output { format: if self.json { Format::Json } else { self.format }, quiet: false }
+    }
+}
+
+// options of earlier versions and the commands they stand for
+const LEGACY_OPTIONS: &[(&str, &[&str])] = &[
+    ("-as", &["section", "add"]),
+    ("-rs", &["section", "remove"]),
+    ("-ls", &["section", "list"]),
+    ("-ae", &["entry", "add"]),
+    ("-ue", &["entry", "update"]),
+    ("-re", &["entry", "remove"]),
+    ("-lv", &["entry", "get"]),
+    ("-lk", &["entry", "list"]),
+    ("-secret", &["entry", "secret"]),
+    ("-unsecret", &["entry", "unsecret"]),
+    ("-hist", &["entry", "history"]),
+    ("-tag", &["tag", "add"]),
+    ("-untag", &["tag", "remove"]),
+    ("-lt", &["tag", "list"]),
+    ("-nb", &["notebook"]),
+    ("-find", &["find"]),
+    ("-backups", &["backup", "list"]),
+    ("-restore", &["backup", "restore"]),
+    ("-encrypt", &["encrypt"]),
+    ("-decrypt", &["decrypt"]),
+    ("-fsck", &["fsck"]),
+    ("-history", &["history"]),
+    ("-undo", &["undo"]),
+    ("-tui", &["tui"]),
+    ("-edit", &["edit"]),
+    ("-env", &["env"]),
+    ("-exec", &["exec"]),
+    ("-help", &["help"])
+];
+
+// global options that may come before a command, and whether each takes a value
+const GLOBAL_OPTIONS: &[(&str, bool)] = &[("-n", true), ("--notebook", true), ("--format", true), ("--json", false), ("--dry-run", false)];
+
+/// Replaces an option of an earlier version, e.g. `-as`, with the command it stands for.
+/// Only the first argument after any global options is replaced, so values are never touched
+pub fn translate_legacy(args: Vec<String>) -> Vec<String> {
+    let mut i = 1;
+    while let Some(arg) = args.get(i) {
+        match GLOBAL_OPTIONS.iter().find(|(option, _)| option == arg) {
+            Some((_, takes_value)) => i += if *takes_value { 2 } else { 1 },
+            None if arg.starts_with("--format=") || arg.starts_with("--notebook=") => i += 1,
+            None => break
+        }
+    }
+
+    let replacement = match args.get(i).and_then(|arg| LEGACY_OPTIONS.iter().find(|(option, _)| option == arg)) {
+        Some((_, command)) => command,
+        None => return args
+    };
+
+    let mut translated = args[..i].to_vec();
+    translated.extend(replacement.iter().map(|word| word.to_string()));
+    translated.extend(args[i + 1..].iter().cloned());
+    translated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn legacy_options_become_commands() {
+        assert_eq!(translate_legacy(args("kn -ae leaders atreides leto")), args("kn entry add leaders atreides leto"));
+        assert_eq!(translate_legacy(args("kn -n work -ls")), args("kn -n work section list"));
+        assert_eq!(translate_legacy(args("kn --dry-run --format tsv -rs work")), args("kn --dry-run --format tsv section remove work"));
+        assert_eq!(translate_legacy(args("kn entry update key -ls")), args("kn entry update key -ls"));
+        assert_eq!(translate_legacy(args("kn -exec work -- env -lv")), args("kn exec work -- env -lv"));
+
+        let cli = Cli::try_parse_from(translate_legacy(args("kn -ae leaders atreides leto of caladan --secret"))).unwrap();
+        match cli.command {
+            Command::Entry(EntryCommand::Add { value, secret, .. }) => {
+                assert_eq!(value.join(" "), "leto of caladan");
+                assert!(secret);
+            },
+            command => panic!("parsed as {:?}", command)
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Shell completion for kn. The scripts clap generates complete commands and flags, and each is
+//! followed by a hook that asks the hidden `kn __complete` command for the sections, keys, tags or
+//! notebooks that fit the word being completed
+
+use std::{env, io::{self, Write}, error::Error};
+
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::{generate, Shell};
+
+use crate::cli::{Cli, translate_legacy};
+
+/// shells completion scripts can be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish
+}
+
+/// what a word on the command line names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Section,
+    Key,
+    Tag,
+    Notebook,
+    /// a key or a section
+    KeyOrSection
+}
+
+// the positional arguments of each command that name something in a notebook. the last
+// target of a command taking any number of arguments repeats
+const TARGETS: &[(&[&str], &[Target], bool)] = &[
+    (&["section", "remove"], &[Target::Section], false),
+    (&["section", "list"], &[Target::Section], false),
+    (&["entry", "add"], &[Target::Section], false),
+    (&["entry", "update"], &[Target::Key], false),
+    (&["entry", "remove"], &[Target::Key], false),
+    (&["entry", "get"], &[Target::Key], false),
+    (&["entry", "secret"], &[Target::Key], false),
+    (&["entry", "unsecret"], &[Target::Key], false),
+    (&["entry", "history"], &[Target::Key], false),
+    (&["tag", "add"], &[Target::Key, Target::Tag], false),
+    (&["tag", "remove"], &[Target::Key, Target::Tag], false),
+    (&["tag", "list"], &[Target::Tag], true),
+    (&["notebook", "use"], &[Target::Notebook], false),
+    (&["notebook", "delete"], &[Target::Notebook], false),
+    (&["edit"], &[Target::KeyOrSection], false),
+    (&["env"], &[Target::Section], false),
+    (&["exec"], &[Target::Section], false)
+];
+
+// flags that take a value, so the word after them is not a positional argument
+const FLAGS_WITH_VALUES: &[&str] = &["-n", "--notebook", "--format", "--since", "--sort", "--shell"];
+
+const BASH_HOOK: &str = r#"
+_kn_with_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local names
+    mapfile -t names < <(kn __complete -- "${COMP_WORDS[@]:1:COMP_CWORD-1}" 2>/dev/null)
+    if [ ${#names[@]} -gt 0 ]; then
+        COMPREPLY=($(compgen -W "${names[*]}" -- "$cur"))
+        return 0
+    fi
+    _kn "$@"
+}
+complete -F _kn_with_names -o nosort -o bashdefault -o default kn
+"#;
+
+const ZSH_HOOK: &str = r#"
+_kn_with_names() {
+    local -a names
+    names=("${(@f)$(kn __complete -- "${(@)words[2,CURRENT-1]}" 2>/dev/null)}")
+    if [[ -n "${names[1]}" ]]; then
+        compadd -a names
+        return 0
+    fi
+    _kn "$@"
+}
+compdef _kn_with_names kn
+"#;
+
+const FISH_HOOK: &str = r#"
+complete -c kn -f -a '(kn __complete -- (commandline -opc)[2..-1] 2>/dev/null)'
+"#;
+
+/// Writes the completion script for a shell to stdout
+pub fn write_script(shell: CompletionShell) -> Result<(), Box<dyn Error>> {
+    let (clap_shell, hook) = match shell {
+        CompletionShell::Bash => (Shell::Bash, BASH_HOOK),
+        CompletionShell::Zsh => (Shell::Zsh, ZSH_HOOK),
+        CompletionShell::Fish => (Shell::Fish, FISH_HOOK)
+    };
+
+    let mut script = Vec::new();
+    generate(clap_shell, &mut Cli::command(), "kn", &mut script);
+    script.extend_from_slice(hook.as_bytes());
+    io::stdout().write_all(&script)?;
+
+    Ok(())
+}
+
+/// Prints the names that can complete the next word, given the words typed so far. Prints nothing
+/// when the next word is not a name, or the notebook cannot be read without asking for a passphrase
+pub fn write_names(words: &[String]) -> Result<(), Box<dyn Error>> {
+    let (target, notebook) = match target_of(words) {
+        (Some(target), notebook) => (target, notebook),
+        (None, _) => return Ok(())
+    };
+
+    let notebooks = keydata::Notebooks::new()?;
+    if target == Target::Notebook {
+        for name in notebooks.list()? {
+            println!("{}", name);
+        }
+        return Ok(())
+    }
+
+    let notebook_name = match notebook {
+        Some(name) => name,
+        None => notebooks.default_notebook()?
+    };
+    if !notebooks.exists(&notebook_name) {
+        return Ok(())
+    }
+    let mut file = notebooks.open(&notebook_name)?;
+    if keydata::is_encrypted(&file.filepath)? {
+        match env::var("KEYNOTES_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => file.set_passphrase(Some(&passphrase)),
+            _ => return Ok(())
+        }
+    }
+    // completion runs on every tab, so it leaves any temp file of an interrupted write for the next command
+    file.load_data_read_only(&keydata::ParseOptions::lenient())?;
+
+    let mut names: Vec<String> = match target {
+        Target::Section => file.get_sections().keys().cloned().collect(),
+        Target::Key => file.get_sections().values().flat_map(|section| section.data.keys().cloned()).collect(),
+        Target::Tag => file.all_tags().into_iter().map(|(tag, _)| tag).collect(),
+        Target::KeyOrSection => file.get_sections().values()
+            .flat_map(|section| section.data.keys().cloned().chain(Some(section.name.clone())))
+            .collect(),
+        Target::Notebook => Vec::new()
+    };
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+
+    Ok(())
+}
+
+/// Works out what the next word names from the words typed so far, and the notebook picked with -n
+pub fn target_of(words: &[String]) -> (Option<Target>, Option<String>) {
+    let args = translate_legacy(Some("kn".to_string()).into_iter().chain(words.iter().cloned()).collect());
+
+    let mut notebook = None;
+    let mut positionals: Vec<&str> = Vec::new();
+    let mut params = args.iter().skip(1);
+    while let Some(word) = params.next() {
+        if FLAGS_WITH_VALUES.contains(&word.as_str()) {
+            let value = params.next();
+            if word == "-n" || word == "--notebook" {
+                notebook = value.cloned();
+            }
+        }
+        else if !word.starts_with('-') {
+            positionals.push(word);
+        }
+    }
+
+    for (path, targets, repeats) in TARGETS {
+        if positionals.len() < path.len() || positionals[..path.len()] != path[..] {
+            continue;
+        }
+        let position = positionals.len() - path.len();
+        let target = match targets.get(position) {
+            Some(target) => Some(*target),
+            None if *repeats => targets.last().copied(),
+            None => None
+        };
+        return (target, notebook);
+    }
+
+    (None, notebook)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(line: &str) -> Option<Target> {
+        let words: Vec<String> = line.split_whitespace().map(|word| word.to_string()).collect();
+        target_of(&words).0
+    }
+
+    #[test]
+    fn next_word_target_follows_command() {
+        assert_eq!(target("entry get"), Some(Target::Key));
+        assert_eq!(target("-lv"), Some(Target::Key));
+        assert_eq!(target("-re"), Some(Target::Key));
+        assert_eq!(target("-ae"), Some(Target::Section));
+        assert_eq!(target("-ae leaders"), None);
+        assert_eq!(target("-rs"), Some(Target::Section));
+        assert_eq!(target("tag add atreides"), Some(Target::Tag));
+        assert_eq!(target("tag list work old"), Some(Target::Tag));
+        assert_eq!(target("-n work --json entry remove"), Some(Target::Key));
+        assert_eq!(target("notebook use"), Some(Target::Notebook));
+        assert_eq!(target("entry"), None);
+        assert_eq!(target("-edit"), Some(Target::KeyOrSection));
+
+        let words: Vec<String> = vec!["-n".to_string(), "work".to_string(), "-lv".to_string()];
+        assert_eq!(target_of(&words), (Some(Target::Key), Some("work".to_string())));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fs, error::Error, path::{Path, PathBuf}};
+
+use crate::{KeynoteFile, JournalRecord};
+
+impl KeynoteFile {
+    /// Makes changes to a copy of the file, and returns the journal records they would add to the file's
+    /// journal. Records of earlier operations reversed by the changes are returned marked as undone.
+    /// The file, its journal and its history are left as they are
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - function making the changes, given the copy
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_dry_run_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    ///
+    /// let records = kn_file.dry_run(|copy| copy.add_entry("leaders", "atreides", "leto")).unwrap();
+    /// assert_eq!(records[0].operation.to_string(), "added entry 'atreides' to 'leaders'");
+    /// assert!(!kn_file.contains_key("atreides"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn dry_run<F>(&self, changes: F) -> Result<Vec<JournalRecord>, Box<dyn Error>>
+        where F: FnOnce(&mut KeynoteFile) -> Result<(), Box<dyn Error>> {
+        let before = self.journal()?;
+
+        let mut copy = KeynoteFile::with_path(&dry_run_filepath(&self.filepath));
+        copy.sections = self.sections.clone();
+        copy.journaling = true;
+        copy.versioning = true;
+        copy.cipher = self.cipher.clone();
+        copy.secret_cipher = self.secret_cipher.clone();
+
+        let copies = [(self.filepath.clone(), copy.filepath.clone()),
+                      (self.journal_filepath(), copy.journal_filepath()),
+                      (self.history_filepath(), copy.history_filepath())];
+        let result = copy_files(&copies).and_then(|_| changes(&mut copy)).and_then(|_| copy.journal());
+        for (_, to) in &copies {
+            if to.exists() {
+                fs::remove_file(to)?;
+            }
+        }
+
+        let records = result?.into_iter().filter(|record| match before.iter().find(|r| r.seq == record.seq) {
+            Some(earlier) => record.undone && !earlier.undone,
+            None => true
+        });
+        Ok(records.collect())
+    }
+}
+
+// copies each file that exists, so the copy starts from the same state
+fn copy_files(copies: &[(PathBuf, PathBuf)]) -> Result<(), Box<dyn Error>> {
+    for (from, to) in copies {
+        if from.exists() {
+            fs::copy(from, to)?;
+        }
+    }
+    Ok(())
+}
+
+// path of the copy changes are made to. the temp prefix keeps it out of the list of notebooks
+fn dry_run_filepath(filepath: &Path) -> PathBuf {
+    let filename = filepath.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    filepath.with_file_name(format!("_kntemp_dryrun_{}", filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::KeynoteFile;
+
+    #[test]
+    fn dry_run_leaves_file_and_journal_alone() {
+        let mut kn_file = KeynoteFile::new("kntest_dry_run.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        let data = fs::read_to_string(&kn_file.filepath).unwrap();
+        let journal = fs::read_to_string(kn_file.journal_filepath()).unwrap();
+
+        let records = kn_file.dry_run(|copy| {
+            copy.remove_section("leaders")?;
+            copy.undo(1)?;
+            copy.undo(1).map(|_| ())
+        }).unwrap();
+        let changes: Vec<(String, bool)> = records.iter().map(|r| (r.operation.to_string(), r.undone)).collect();
+        assert_eq!(changes, vec![("added entry 'atreides' to 'leaders'".to_string(), true),
+                                 ("removed section 'leaders' (1 entry)".to_string(), true)]);
+
+        assert!(kn_file.dry_run(|copy| copy.remove_entry("harkonnen")).is_err());
+
+        assert_eq!(fs::read_to_string(&kn_file.filepath).unwrap(), data);
+        assert_eq!(fs::read_to_string(kn_file.journal_filepath()).unwrap(), journal);
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+        assert!(!kn_file.filepath.with_file_name("_kntemp_dryrun_kntest_dry_run.dat").exists());
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! A plain text form of a section's entries, one `key = value` per line, for editing outside of kn.
+//! Edited text is read back with `entries_from_text` and applied with `replace_entries` in one write
+
+use std::{collections::HashSet, error::Error};
+
+use crate::{FileItem, KeynoteFile, Metadata, Operation, VersionEvent, SECRET_MASK, check_key, is_secret_value, masked_value,
+            error::{already_exists, corrupt, invalid_input, not_found}};
+
+/// What `replace_entries` changed, by key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryChanges {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>
+}
+
+impl EntryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Writes entries in the form read by `entries_from_text`, sorted by key. Secret values are masked
+///
+/// # Arguments
+///
+/// * `section_name` - name of the section, shown in the comment heading the text
+/// * `entries` - keys and values, as held in the Section
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// let text = entries_to_text("leaders", &[("atreides", "leto")]);
+/// assert!(text.ends_with("atreides = leto\n"));
+/// assert_eq!(entries_from_text(&text).unwrap(), vec![("atreides".to_string(), "leto".to_string())]);
+/// ```
+pub fn entries_to_text(section_name: &str, entries: &[(&str, &str)]) -> String {
+    let mut entries = entries.to_vec();
+    entries.sort();
+
+    let mut text = format!("# entries of {}, one per line as: key = value\n", section_name);
+    text.push_str(&format!("# delete a line to remove its entry. leave {} as is to keep a secret value\n", SECRET_MASK));
+    for (key, value) in entries {
+        text.push_str(&format!("{} = {}\n", key, masked_value(value)));
+    }
+    text
+}
+
+/// Reads entries from the form written by `entries_to_text`. Blank lines and lines starting with
+/// '#' are skipped. Keys are checked with `check_key` and may appear only once. A value is the rest of
+/// the line after the first '=' and the space following it, so whitespace in values is kept
+///
+/// # Arguments
+///
+/// * `text` - edited text
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// let entries = entries_from_text("# leaders\natreides = leto\n\nharkonnen=vladimir\n").unwrap();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries_from_text("atreides leto").unwrap_err().to_string(), "line 1: expected key = value");
+/// assert_eq!(entries_from_text("token =  padded ").unwrap()[0].1, " padded ");
+/// ```
+pub fn entries_from_text(text: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    let mut keys = HashSet::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        // keys hold no whitespace, values are kept as written
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.strip_prefix(' ').unwrap_or(value)),
+            None => return Err(invalid_input(format!("line {}: expected key = value", i + 1)))
+        };
+        check_key(key).map_err(|e| invalid_input(format!("line {}: {}", i + 1, e)))?;
+        if !keys.insert(key) {
+            return Err(invalid_input(format!("line {}: key {} appears more than once", i + 1, key)));
+        }
+        entries.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(entries)
+}
+
+impl KeynoteFile {
+    /// Makes a section hold exactly the given entries, adding, updating and removing entries as
+    /// needed in a single write. Nothing is written if any entry is invalid. Secret entries stay
+    /// secret, and keep their value when given `SECRET_MASK`
+    ///
+    /// # Arguments
+    ///
+    /// * `section_name` - section whose entries are replaced
+    /// * `entries` - keys and values the section should hold
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_replace_entries_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.add_entry("leaders", "corrino", "shaddam").unwrap();
+    ///
+    /// let entries = entries_from_text("atreides = paul\nharkonnen = vladimir").unwrap();
+    /// let changes = kn_file.replace_entries("leaders", &entries).unwrap();
+    /// assert_eq!((changes.added, changes.updated, changes.removed),
+    ///            (vec!["harkonnen".to_string()], vec!["atreides".to_string()], vec!["corrino".to_string()]));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn replace_entries(&mut self, section_name: &str, entries: &[(String, String)]) -> Result<EntryChanges, Box<dyn Error>> {
+        let current = match self.get_section(section_name) {
+            Some(section) => section.data.clone(),
+            None => return Err(not_found(format!("cannot edit '{}'. that section does not exist", section_name)))
+        };
+
+        // check every entry, and work out how each will be stored, before anything is written
+        let mut stored: Vec<(String, String)> = Vec::new();
+        for (key, value) in entries {
+            check_key(key)?;
+            if value.contains('\n') || value.contains('\r') {
+                return Err(invalid_input(format!("the value of {} must be a single line", key)));
+            }
+            if stored.iter().any(|(k, _)| k == key) {
+                return Err(invalid_input(format!("key {} appears more than once", key)));
+            }
+            let value = match current.get(key) {
+                Some(old) if is_secret_value(old) && value == SECRET_MASK => old.clone(),
+                Some(old) if is_secret_value(old) => self.seal_secret(key, value)?,
+                Some(_) => value.clone(),
+                None if self.contains_key(key) => return Err(already_exists(format!("key: {} already exists in another section", key))),
+                None => value.clone()
+            };
+            stored.push((key.clone(), value));
+        }
+
+        let mut changes = EntryChanges::default();
+        for (key, value) in &stored {
+            match current.get(key) {
+                None => changes.added.push(key.clone()),
+                Some(old) if old != value => changes.updated.push(key.clone()),
+                Some(_) => {}
+            }
+        }
+        let mut removed: Vec<String> = current.keys().filter(|key| !stored.iter().any(|(k, _)| k == *key)).cloned().collect();
+        removed.sort();
+        changes.removed = removed;
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+
+        let mut items = self.read_items()?;
+        for key in &changes.removed {
+            if let Some(index) = KeynoteFile::find_entry_item(&items, key) {
+                items.remove(index);
+            }
+        }
+        let mut metadata = Vec::new();
+        for (key, value) in stored.iter().filter(|(key, _)| changes.updated.contains(key)) {
+            let index = match KeynoteFile::find_entry_item(&items, key) {
+                Some(index) => index,
+                None => return Err(corrupt("error: file corrupted"))
+            };
+            items[index].line = KeynoteFile::build_entry_string(key, value);
+            metadata.push((key.clone(), KeynoteFile::touch_item(&mut items[index])));
+        }
+        let header = match KeynoteFile::find_section_item(&items, section_name) {
+            Some(header) => header,
+            None => return Err(corrupt("error: file corrupted"))
+        };
+        for (offset, (key, value)) in stored.iter().filter(|(key, _)| changes.added.contains(key)).enumerate() {
+            let created = Metadata::created_now();
+            items.insert(header + 1 + offset, FileItem { line: KeynoteFile::build_entry_string(key, value), meta: Some(created.clone()) });
+            metadata.push((key.clone(), created));
+        }
+        let section_metadata = KeynoteFile::touch_item(&mut items[header]);
+
+        self.write_items(&items)?;
+
+        if let Some(section) = self.get_section(section_name) {
+            for key in &changes.removed {
+                section.data.remove(key);
+                section.entry_metadata.remove(key);
+            }
+            for (key, value) in &stored {
+                section.add_entry(key, value);
+            }
+            section.entry_metadata.extend(metadata);
+            section.metadata = section_metadata;
+        }
+
+        // the edit is journaled as one operation, so a single undo reverses all of it
+        let mut added = Vec::new();
+        let mut updated = Vec::new();
+        for (key, value) in &stored {
+            if changes.updated.contains(key) {
+                let old_value = &current[key];
+                self.record_version(section_name, key, old_value, VersionEvent::Updated)?;
+                updated.push((key.clone(), old_value.clone(), value.clone()));
+            }
+            else if changes.added.contains(key) {
+                added.push((key.clone(), value.clone()));
+            }
+        }
+        let mut removed = Vec::new();
+        for key in &changes.removed {
+            let value = &current[key];
+            self.record_version(section_name, key, value, VersionEvent::Removed)?;
+            removed.push((key.clone(), value.clone()));
+        }
+        self.record_operation(Operation::EditEntries { section: section_name.to_string(), added, updated, removed })?;
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::ErrorKind;
+
+    #[test]
+    fn replace_entries_checks_everything_before_writing() {
+        let mut kn_file = KeynoteFile::new("kntest_replace_entries.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.set_secret_passphrase(Some("arrakis"));
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_section("fremen").unwrap();
+        kn_file.add_entry("fremen", "stilgar", "naib").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.add_secret_entry("leaders", "spice", "melange").unwrap();
+        let before = fs::read_to_string(&kn_file.filepath).unwrap();
+
+        let text = entries_to_text("leaders", &[("atreides", "leto"), ("spice", kn_file.get_value_from_key("spice").unwrap())]);
+        assert!(text.contains("spice = ****"));
+
+        // an entry clashing with another section stops the whole edit
+        let kind = |result: Result<EntryChanges, Box<dyn Error>>| ErrorKind::of(result.unwrap_err().as_ref());
+        let clash = entries_from_text(&format!("{}harkonnen = vladimir\nstilgar = usul\n", text)).unwrap();
+        assert_eq!(kind(kn_file.replace_entries("leaders", &clash)), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&kn_file.filepath).unwrap(), before);
+
+        // values are read back as written, so an untouched edit changes nothing, whitespace included
+        kn_file.add_entry("fremen", "token", " padded ").unwrap();
+        let fremen = entries_to_text("fremen", &[("stilgar", "naib"), ("token", " padded ")]);
+        assert!(kn_file.replace_entries("fremen", &entries_from_text(&fremen).unwrap()).unwrap().is_empty());
+        kn_file.load_data().unwrap();
+        assert_eq!(kn_file.get_value_from_key("token"), Some(" padded "));
+
+        // a masked secret keeps its value, a new one is encrypted
+        let unchanged = entries_from_text(&text).unwrap();
+        assert!(kn_file.replace_entries("leaders", &unchanged).unwrap().is_empty());
+        let edited = entries_from_text("atreides = paul\nspice = sapho\n").unwrap();
+        let changes = kn_file.replace_entries("leaders", &edited).unwrap();
+        assert_eq!(changes.updated, vec!["atreides", "spice"]);
+        assert!(kn_file.is_secret("spice"));
+        assert_eq!(kn_file.reveal("spice").unwrap(), "sapho");
+
+        let removed = entries_from_text("spice = ****\n").unwrap();
+        assert_eq!(kn_file.replace_entries("leaders", &removed).unwrap().removed, vec!["atreides"]);
+        kn_file.undo(1).unwrap();
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("paul"));
+
+        // an edit is journaled as one operation, so a single undo reverses every change in it
+        let edited = entries_from_text("atreides = leto\nharkonnen = vladimir\n").unwrap();
+        let changes = kn_file.replace_entries("leaders", &edited).unwrap();
+        assert_eq!((changes.added.len(), changes.updated.len(), changes.removed.len()), (1, 1, 1));
+        assert_eq!(kn_file.journal().unwrap().last().unwrap().operation.to_string(), "edited section 'leaders' (1 added, 1 updated, 1 removed)");
+        kn_file.undo(1).unwrap();
+        kn_file.load_data().unwrap();
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("paul"));
+        assert!(!kn_file.contains_key("harkonnen"));
+        assert_eq!(kn_file.reveal("spice").unwrap(), "sapho");
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Editing text in the user's editor. The text is written to a temp file only the user can read,
+//! in the keynotes folder, so it stays private even if the editor replaces the file when saving.
+//! The file is removed once the editor exits, and by the next kn to start if kn exits first
+
+use std::{env, fs::{self, File}, error::Error, io::{self, Write}, path::{Path, PathBuf}, process::{self, Command}};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use keydata::{ErrorKind, KeynoteError};
+
+const TEMP_FILE_PREFIX: &str = "_kntemp_edit_";
+const LOCK_EXTENSION: &str = "lock";
+
+// used when neither VISUAL nor EDITOR is set
+#[cfg(unix)]
+const DEFAULT_EDITOR: &str = "vi";
+#[cfg(not(unix))]
+const DEFAULT_EDITOR: &str = "notepad";
+
+/// Opens text in $VISUAL, or $EDITOR, and returns the text once the editor exits
+///
+/// # Arguments
+///
+/// * `folder` - folder to keep the temp file in
+/// * `text` - text to edit
+pub fn edit_text(folder: &Path, text: &str) -> Result<String, Box<dyn Error>> {
+    let path = temp_path(folder, process::id());
+    // held until the temp file is removed, so other kn processes leave it alone
+    let lock = open_private(&path.with_extension(LOCK_EXTENSION), false)?;
+    lock.lock()?;
+    open_private(&path, true)?.write_all(text.as_bytes())?;
+
+    let result = run_editor(&path).and_then(|_| Ok(fs::read_to_string(&path)?));
+    fs::remove_file(&path)?;
+    drop(lock);
+    fs::remove_file(path.with_extension(LOCK_EXTENSION))?;
+    result
+}
+
+/// Removes the temp files of edits whose kn exited before the editor did. A kn still editing holds
+/// the lock file next to its temp file, so its files are kept
+///
+/// # Arguments
+///
+/// * `folder` - folder the temp files are kept in
+pub fn remove_stale_temps(folder: &Path) -> Result<(), Box<dyn Error>> {
+    if !folder.exists() {
+        return Ok(());
+    }
+
+    for dir_entry in fs::read_dir(folder)? {
+        let name = dir_entry?.file_name().to_string_lossy().to_string();
+        let pid = match name.strip_prefix(TEMP_FILE_PREFIX).and_then(|rest| rest.split_once('.')) {
+            Some((pid, _)) => match pid.parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => continue
+            },
+            None => continue
+        };
+
+        let path = temp_path(folder, pid);
+        let lock_path = path.with_extension(LOCK_EXTENSION);
+        let lock = open_private(&lock_path, false)?;
+        if lock.try_lock().is_err() {
+            continue;
+        }
+        // the files may already be gone, removed by the kn that wrote them or by another one starting
+        for path in [&path, &lock_path] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Box::new(e)),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_editor(path: &Path) -> Result<(), Box<dyn Error>> {
+    let editor = env::var("VISUAL").ok().filter(|editor| !editor.trim().is_empty())
+        .or_else(|| env::var("EDITOR").ok().filter(|editor| !editor.trim().is_empty()))
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+
+    // the editor may be given with arguments, e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = Command::new(program).args(words).arg(path).status()
+        .map_err(|e| KeynoteError::new(ErrorKind::Other, format!("unable to start editor '{}': {}", editor, e)))?;
+    if !status.success() {
+        return Err(Box::new(KeynoteError::new(ErrorKind::Other, format!("editor '{}' failed ({}). nothing changed", editor, status))));
+    }
+    Ok(())
+}
+
+// the text being edited is kept in _kntemp_edit_<pid>.txt, locked through _kntemp_edit_<pid>.lock
+fn temp_path(folder: &Path, pid: u32) -> PathBuf {
+    folder.join(format!("{}{}.txt", TEMP_FILE_PREFIX, pid))
+}
+
+// opens a file only the user can read, creating it if needed
+fn open_private(path: &Path, truncate: bool) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.write(true).create(true).truncate(truncate).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_temps_are_removed_unless_still_edited() {
+        let folder = env::temp_dir().join("kntest_editor_stale_temps");
+        fs::create_dir_all(&folder).unwrap();
+        let stale = temp_path(&folder, 1);
+        fs::write(&stale, "spice = melange").unwrap();
+        let editing = temp_path(&folder, 2);
+        let lock = open_private(&editing.with_extension(LOCK_EXTENSION), false).unwrap();
+        lock.lock().unwrap();
+        fs::write(&editing, "spice = sapho").unwrap();
+        fs::write(folder.join("_kntemp_edit_notes.txt"), "").unwrap();
+
+        remove_stale_temps(&folder).unwrap();
+        assert!(!stale.exists() && !stale.with_extension(LOCK_EXTENSION).exists());
+        assert!(editing.exists());
+        assert!(folder.join("_kntemp_edit_notes.txt").exists());
+
+        drop(lock);
+        remove_stale_temps(&folder).unwrap();
+        assert!(!editing.exists());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::{fs, io::Write, cell::RefCell, error::Error, path::Path};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce, aead::{Aead, OsRng, Payload, rand_core::RngCore}};
+
+use crate::{KeynoteFile, temp_filepath, error::{corrupt, invalid_input}, permissions::{copy_permissions, create_private_file, private_open_options},
+            recovery::lock_writes};
+
+// an encrypted file is laid out as
+//   magic | argon2 memory cost | time cost | parallelism (u32 little endian each) | salt | nonce | ciphertext
+// the header up to the ciphertext is authenticated along with it
+const MAGIC: &[u8] = b"KNENC01\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+
+/// Checks if the file at a path is encrypted. A file that does not exist is not encrypted
+///
+/// # Arguments
+///
+/// * `filepath` - path of the file to check
+///
+/// # Examples    ///
+/// ```
+/// use std::fs;
+/// use keydata::*;
+///
+/// let mut kn_file = KeynoteFile::new("kntest_is_encrypted_doc.dat").unwrap();
+/// kn_file.add_section("leaders").unwrap();
+/// assert!(!is_encrypted(&kn_file.filepath).unwrap());
+///
+/// fs::remove_file(kn_file.filepath);  // remove the test file
+/// ```
+pub fn is_encrypted(filepath: &Path) -> Result<bool, Box<dyn Error>> {
+    if !filepath.exists() {
+        return Ok(false);
+    }
+    Ok(is_sealed(&fs::read(filepath)?))
+}
+
+/// Checks if bytes are in the encrypted file layout
+pub(crate) fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Passphrase used to encrypt and decrypt files, with the key last derived from it. Deriving a key is
+/// deliberately slow, so it is done once per salt and new files are written with the same salt
+#[derive(Clone)]
+pub(crate) struct Cipher {
+    passphrase: String,
+    derived: RefCell<Option<([u8; SALT_LEN], Params, Key)>>
+}
+
+impl Cipher {
+    pub(crate) fn new(passphrase: &str) -> Cipher {
+        Cipher { passphrase: passphrase.to_string(), derived: RefCell::new(None) }
+    }
+
+    /// Encrypts data into the encrypted file layout
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let derived = self.derived.borrow().clone();
+        let (salt, params, key) = match derived {
+            Some(derived) => derived,
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                let params = Params::default();
+                let key = self.derive_key(&salt, &params)?;
+                (salt, params, key)
+            }
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        sealed.extend_from_slice(MAGIC);
+        for value in [params.m_cost(), params.t_cost(), params.p_cost()] {
+            sealed.extend_from_slice(&value.to_le_bytes());
+        }
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+
+        let ciphertext = XChaCha20Poly1305::new(&key)
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: &sealed })
+            .map_err(|_| "error: unable to encrypt data")?;
+        sealed.extend_from_slice(&ciphertext);
+
+        *self.derived.borrow_mut() = Some((salt, params, key));
+        Ok(sealed)
+    }
+
+    /// Decrypts data in the encrypted file layout, failing if the passphrase is wrong or the data was altered
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if sealed.len() < HEADER_LEN || !sealed.starts_with(MAGIC) {
+            return Err(corrupt("error: encrypted file is truncated or not encrypted"));
+        }
+        let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+
+        let cost = |i: usize| {
+            let start = MAGIC.len() + i * 4;
+            u32::from_le_bytes([header[start], header[start + 1], header[start + 2], header[start + 3]])
+        };
+        // the costs are checked before a key is derived with them, as the header is only authenticated after.
+        // files are written with the default costs, anything higher is a damaged or hostile header
+        let defaults = Params::default();
+        if cost(0) > defaults.m_cost() || cost(1) > defaults.t_cost() || cost(2) > defaults.p_cost() {
+            return Err(corrupt("error: invalid encryption header: key derivation costs are higher than kn writes"));
+        }
+        let params = Params::new(cost(0), cost(1), cost(2), None).map_err(|e| corrupt(format!("error: invalid encryption header: {}", e)))?;
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&header[MAGIC.len() + 12..MAGIC.len() + 12 + SALT_LEN]);
+        let nonce = XNonce::from_slice(&header[HEADER_LEN - NONCE_LEN..]);
+
+        let derived = self.derived.borrow().clone();
+        let key = match derived {
+            Some((derived_salt, derived_params, key)) if derived_salt == salt && derived_params == params => key,
+            _ => self.derive_key(&salt, &params)?
+        };
+
+        let plaintext = XChaCha20Poly1305::new(&key)
+            .decrypt(nonce, Payload { msg: ciphertext, aad: header })
+            .map_err(|_| invalid_input("error: unable to decrypt. the passphrase is wrong or the file is damaged"))?;
+
+        *self.derived.borrow_mut() = Some((salt, params, key));
+        Ok(plaintext)
+    }
+
+    fn derive_key(&self, salt: &[u8], params: &Params) -> Result<Key, Box<dyn Error>> {
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("error: unable to derive key: {}", e))?;
+        Ok(key)
+    }
+}
+
+impl KeynoteFile {
+    /// Sets the passphrase used to read and write encrypted files. While a passphrase is set the data file,
+    /// journal and history are written encrypted. Plaintext files are still read, so setting a passphrase
+    /// encrypts a plaintext file the next time it is written. None turns encryption off
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - passphrase to derive the encryption key from, or None
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_passphrase_doc.dat").unwrap();
+    /// kn_file.set_passphrase(Some("arrakis"));
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// assert!(is_encrypted(&kn_file.filepath).unwrap());
+    ///
+    /// kn_file.load_data().unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn set_passphrase(&mut self, passphrase: Option<&str>) {
+        self.cipher = passphrase.map(Cipher::new);
+    }
+
+    /// Encrypts the data file, along with its journal, history and backups, with a passphrase
+    /// that is then used for every later read and write
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - passphrase to derive the encryption key from
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_encrypt_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    ///
+    /// kn_file.encrypt("arrakis").unwrap();
+    /// assert!(is_encrypted(&kn_file.filepath).unwrap());
+    /// assert!(kn_file.encrypt("arrakis").is_err());
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn encrypt(&mut self, passphrase: &str) -> Result<(), Box<dyn Error>> {
+        if is_encrypted(&self.filepath)? {
+            return Err(invalid_input("data file is already encrypted"));
+        }
+
+        let files = self.read_related_files()?;
+        self.set_passphrase(Some(passphrase));
+        self.write_related_files(files)
+    }
+
+    /// Decrypts the data file, along with its journal, history and backups, using the passphrase
+    /// already set, then turns encryption off
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_decrypt_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.encrypt("arrakis").unwrap();
+    ///
+    /// kn_file.decrypt().unwrap();
+    /// assert!(!is_encrypted(&kn_file.filepath).unwrap());
+    /// assert!(fs::read_to_string(&kn_file.filepath).unwrap().starts_with("<leaders>"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn decrypt(&mut self) -> Result<(), Box<dyn Error>> {
+        if !is_encrypted(&self.filepath)? {
+            return Err(invalid_input("data file is not encrypted"));
+        }
+        if self.cipher.is_none() {
+            return Err(invalid_input("a passphrase is needed to decrypt the data file"));
+        }
+
+        let files = self.read_related_files()?;
+        self.set_passphrase(None);
+        self.write_related_files(files)
+    }
+
+    /// Reads a file written through this KeynoteFile, decrypting it if needed. A missing file reads as empty
+    pub(crate) fn read_text_file(&self, path: &Path) -> Result<String, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(String::new());
+        }
+        let bytes = self.open_bytes(&fs::read(path)?)?;
+        String::from_utf8(bytes).map_err(|_| corrupt(format!("error: {} is not valid text", path.display())))
+    }
+
+    /// Appends text to a file, encrypted if a passphrase is set. An encrypted file is rewritten as a whole
+    pub(crate) fn append_text_file(&self, path: &Path, text: &str) -> Result<(), Box<dyn Error>> {
+        if self.cipher.is_none() && !is_encrypted(path)? {
+            let mut file = private_open_options().append(true).create(true).open(path)?;
+            file.write_all(text.as_bytes())?;
+            return Ok(());
+        }
+
+        let mut contents = self.read_text_file(path)?;
+        contents.push_str(text);
+        replace_file(path, &self.seal_bytes(contents.as_bytes())?)
+    }
+
+    /// Writes a file in full, encrypted if a passphrase is set
+    pub(crate) fn write_text_file(&self, path: &Path, text: &str) -> Result<(), Box<dyn Error>> {
+        replace_file(path, &self.seal_bytes(text.as_bytes())?)
+    }
+
+    /// Encrypts bytes if a passphrase is set, otherwise returns them as is
+    pub(crate) fn seal_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(bytes),
+            None => Ok(bytes.to_vec())
+        }
+    }
+
+    /// Decrypts bytes if they are encrypted, otherwise returns them as is
+    pub(crate) fn open_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if !is_sealed(bytes) {
+            return Ok(bytes.to_vec());
+        }
+        match &self.cipher {
+            Some(cipher) => cipher.open(bytes),
+            None => Err(invalid_input("data file is encrypted. a passphrase is needed to read it"))
+        }
+    }
+
+    pub(crate) fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    // the data file and every file kept alongside it that holds its contents, with their text
+    fn read_related_files(&self) -> Result<Vec<(std::path::PathBuf, String)>, Box<dyn Error>> {
+        let mut paths = vec![self.filepath.clone(), self.journal_filepath(), self.history_filepath()];
+        paths.extend(self.list_backups()?.into_iter().map(|backup| backup.path));
+
+        let mut files = Vec::new();
+        for path in paths.into_iter().filter(|path| path.exists()) {
+            let text = self.read_text_file(&path)?;
+            files.push((path, text));
+        }
+        Ok(files)
+    }
+
+    fn write_related_files(&self, files: Vec<(std::path::PathBuf, String)>) -> Result<(), Box<dyn Error>> {
+        let _lock = lock_writes(&self.filepath)?;
+        for (path, text) in files {
+            replace_file(&path, &self.seal_bytes(text.as_bytes())?)?;
+        }
+        Ok(())
+    }
+}
+
+// writes a file in full through a temp file, so an interrupted write leaves the old version in place
+fn replace_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp_filepath = temp_filepath(path);
+    let mut tmp_file = create_private_file(&tmp_filepath)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.sync_all()?;
+    drop(tmp_file);
+    copy_permissions(path, &tmp_filepath)?;
+
+    fs::rename(tmp_filepath, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let cipher = Cipher::new("arrakis");
+        let sealed = cipher.seal(b"<leaders>\n").unwrap();
+
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(7).any(|w| w == b"leaders"));
+        assert_eq!(cipher.open(&sealed).unwrap(), b"<leaders>\n");
+
+        // a fresh cipher derives the key from the header
+        assert_eq!(Cipher::new("arrakis").open(&sealed).unwrap(), b"<leaders>\n");
+        assert!(Cipher::new("caladan").open(&sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(cipher.open(&tampered).is_err());
+    }
+
+    #[test]
+    fn inflated_key_derivation_costs_are_rejected() {
+        let sealed = Cipher::new("arrakis").seal(b"<leaders>\n").unwrap();
+
+        // a memory cost of 4 TiB is refused before any key is derived
+        let mut inflated = sealed.clone();
+        inflated[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let e = Cipher::new("arrakis").open(&inflated).unwrap_err();
+        assert!(e.to_string().starts_with("error: invalid encryption header"));
+
+        let mut inflated = sealed;
+        inflated[MAGIC.len() + 4..MAGIC.len() + 8].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(Cipher::new("arrakis").open(&inflated).is_err());
+    }
+
+    #[test]
+    fn encrypted_file_needs_passphrase() {
+        let mut kn_file = KeynoteFile::new("kntest_encrypted_file.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.set_versioning(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.update_entry("atreides", "paul").unwrap();
+        kn_file.encrypt("arrakis").unwrap();
+        kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+
+        for path in [&kn_file.filepath, &kn_file.journal_filepath(), &kn_file.history_filepath()] {
+            assert!(is_encrypted(path).unwrap(), "{} not encrypted", path.display());
+        }
+        assert_eq!(kn_file.history("atreides").unwrap()[0].value, "leto");
+        assert_eq!(kn_file.journal().unwrap().len(), 4);
+
+        let mut reader = KeynoteFile::new("kntest_encrypted_file.dat").unwrap();
+        assert!(reader.load_data().is_err());
+        reader.set_passphrase(Some("caladan"));
+        assert!(reader.load_data().is_err());
+        reader.set_passphrase(Some("arrakis"));
+        reader.load_data().unwrap();
+        assert_eq!(reader.get_value_from_key("harkonnen"), Some("vladimir"));
+
+        reader.decrypt().unwrap();
+        assert!(!is_encrypted(&reader.journal_filepath()).unwrap());
+        assert!(fs::read_to_string(&reader.filepath).unwrap().contains("<atreides>paul<~>"));
+
+        fs::remove_file(reader.journal_filepath()).unwrap();
+        fs::remove_file(reader.history_filepath()).unwrap();
+        fs::remove_file(reader.filepath).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Entries as environment variables. Keys must be valid variable names, values are passed on with
+//! their references expanded and secrets revealed
+
+use std::error::Error;
+
+use crate::{KeynoteFile, error::{invalid_input, not_found}};
+
+/// Checks if a key can be used as the name of an environment variable: letters, digits and '_',
+/// not starting with a digit
+///
+/// # Arguments
+///
+/// * `key` - key of an entry
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// assert!(is_env_name("DATABASE_URL"));
+/// assert!(!is_env_name("db.host"));
+/// assert!(!is_env_name("2fa"));
+/// ```
+pub fn is_env_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+impl KeynoteFile {
+    /// Returns the entries of a section, or of every section, as environment variables sorted by name.
+    /// References are expanded and secret values revealed, so a secret passphrase is needed if any
+    /// value used is secret
+    ///
+    /// # Arguments
+    ///
+    /// * `section_name` - section holding the variables, or None for all entries
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_environment_doc.dat").unwrap();
+    /// kn_file.add_section("project").unwrap();
+    /// kn_file.add_entry("project", "HOST", "db.example.com").unwrap();
+    /// kn_file.add_entry("project", "DATABASE_URL", "postgres://${HOST}/app").unwrap();
+    ///
+    /// let vars = kn_file.environment(Some("project")).unwrap();
+    /// assert_eq!(vars[0], ("DATABASE_URL".to_string(), "postgres://db.example.com/app".to_string()));
+    ///
+    /// kn_file.add_entry("project", "db.port", "5432").unwrap();
+    /// assert!(kn_file.environment(Some("project")).is_err());   // db.port is not a variable name
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn environment(&mut self, section_name: Option<&str>) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let mut keys: Vec<String> = match section_name {
+            Some(name) => match self.get_section(name) {
+                Some(section) => section.data.keys().cloned().collect(),
+                None => return Err(not_found(format!("section: '{}' does not exist", name)))
+            },
+            None => self.get_sections().values().flat_map(|section| section.data.keys().cloned()).collect()
+        };
+        keys.sort();
+
+        let invalid: Vec<&str> = keys.iter().filter(|key| !is_env_name(key)).map(|key| key.as_str()).collect();
+        if !invalid.is_empty() {
+            return Err(invalid_input(format!("not valid as environment variable names: {}. names hold letters, digits and _, \
+                                              and do not start with a digit", invalid.join(", "))));
+        }
+
+        let mut vars = Vec::new();
+        for key in keys {
+            let value = self.resolve_revealed(&key)?;
+            vars.push((key, value));
+        }
+        Ok(vars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::{KeynoteFile, ErrorKind};
+
+    #[test]
+    fn environment_reveals_secrets_and_checks_names() {
+        let mut kn_file = KeynoteFile::new("kntest_environment.dat").unwrap();
+        kn_file.set_secret_passphrase(Some("arrakis"));
+        kn_file.add_section("shared").unwrap();
+        kn_file.add_section("project").unwrap();
+        kn_file.add_secret_entry("shared", "password", "melange").unwrap();
+        kn_file.add_entry("project", "PGPASSWORD", "${shared.password}").unwrap();
+        kn_file.add_entry("project", "_USER", "admin").unwrap();
+
+        assert_eq!(kn_file.environment(Some("project")).unwrap(),
+                   [("PGPASSWORD".to_string(), "melange".to_string()), ("_USER".to_string(), "admin".to_string())]);
+        assert_eq!(ErrorKind::of(kn_file.environment(Some("nowhere")).unwrap_err().as_ref()), ErrorKind::NotFound);
+        // every entry of the notebook is used without a section, and password is lower case but valid
+        assert_eq!(kn_file.environment(None).unwrap().len(), 3);
+
+        kn_file.add_entry("project", "2fa", "on").unwrap();
+        kn_file.add_entry("project", "api-key", "x").unwrap();
+        let e = kn_file.environment(Some("project")).unwrap_err();
+        assert_eq!(ErrorKind::of(e.as_ref()), ErrorKind::InvalidInput);
+        assert!(e.to_string().starts_with("not valid as environment variable names: 2fa, api-key."));
+
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
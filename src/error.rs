@@ -0,0 +1,134 @@
+//! Errors raised by the library carry a kind, so callers can tell a missing key from a damaged file
+//! without reading the message
+
+use std::{fmt, io, error::Error};
+
+use crate::{NameError, ParseError};
+
+/// What went wrong, in broad categories
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// a section, key, notebook, backup or version does not exist
+    NotFound,
+    /// a section, key or notebook being added already exists
+    AlreadyExists,
+    /// a name, value or passphrase given is not valid
+    InvalidInput,
+    /// a data, journal or history file is damaged
+    Corrupt,
+    /// reading or writing a file failed
+    Io,
+    /// anything else
+    Other
+}
+
+impl ErrorKind {
+    /// Returns the kind of any error returned by the library. Errors the library did not raise itself
+    /// are `Other`
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - error to classify
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_error_kind_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    ///
+    /// let error = kn_file.add_section("leaders").unwrap_err();
+    /// assert_eq!(ErrorKind::of(error.as_ref()), ErrorKind::AlreadyExists);
+    /// let error = kn_file.add_entry("fremen", "stilgar", "naib").unwrap_err();
+    /// assert_eq!(ErrorKind::of(error.as_ref()), ErrorKind::NotFound);
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn of(error: &(dyn Error + 'static)) -> ErrorKind {
+        if let Some(e) = error.downcast_ref::<KeynoteError>() {
+            e.kind
+        }
+        else if error.is::<NameError>() {
+            ErrorKind::InvalidInput
+        }
+        else if error.is::<ParseError>() {
+            ErrorKind::Corrupt
+        }
+        else if error.is::<io::Error>() {
+            ErrorKind::Io
+        }
+        else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// An error raised by the library, with its kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeynoteError {
+    pub kind: ErrorKind,
+    pub message: String
+}
+
+impl KeynoteError {
+    /// Creates an error of a kind
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - what went wrong
+    /// * `message` - message shown to the user
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> KeynoteError {
+        KeynoteError { kind, message: message.into() }
+    }
+}
+
+impl fmt::Display for KeynoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for KeynoteError {}
+
+pub(crate) fn not_found(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(ErrorKind::NotFound, message))
+}
+
+pub(crate) fn already_exists(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(ErrorKind::AlreadyExists, message))
+}
+
+pub(crate) fn invalid_input(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(ErrorKind::InvalidInput, message))
+}
+
+pub(crate) fn corrupt(message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(ErrorKind::Corrupt, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{KeynoteFile, ParseOptions};
+
+    #[test]
+    fn library_errors_have_kinds() {
+        let mut kn_file = KeynoteFile::new("kntest_error_kinds.dat").unwrap();
+        kn_file.add_section("leaders").unwrap();
+
+        let kind = |result: Result<(), Box<dyn Error>>| ErrorKind::of(result.unwrap_err().as_ref());
+        assert_eq!(kind(kn_file.add_section("bad name")), ErrorKind::InvalidInput);
+        assert_eq!(kind(kn_file.update_entry("atreides", "leto")), ErrorKind::NotFound);
+        assert_eq!(kind(kn_file.tag("atreides", "bad,tag")), ErrorKind::InvalidInput);
+
+        fs::write(&kn_file.filepath, "\t<orphan>value<~>\n").unwrap();
+        assert_eq!(kind(kn_file.load_data_with_options(&ParseOptions::strict()).map(|_| ())), ErrorKind::Corrupt);
+
+        let missing: Box<dyn Error> = Box::new(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(ErrorKind::of(missing.as_ref()), ErrorKind::Io);
+
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+//! Passing entries on as environment variables, either as lines a shell evaluates or by running a
+//! command with them set
+
+use std::{error::Error, process::Command};
+
+use clap::ValueEnum;
+use keydata::{ErrorKind, KeynoteError};
+
+/// syntax of the lines `kn env` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportShell {
+    /// export NAME='value', for sh, bash and zsh
+    Sh,
+    /// set -gx NAME 'value'
+    Fish,
+    /// $env:NAME = 'value'
+    Powershell
+}
+
+/// Returns the line that sets an environment variable in the given shell. Values are single quoted,
+/// so nothing in them is expanded by the shell
+pub fn export_line(shell: ExportShell, name: &str, value: &str) -> String {
+    match shell {
+        ExportShell::Sh => format!("export {}='{}'", name, value.replace('\'', r"'\''")),
+        ExportShell::Fish => format!("set -gx {} '{}'", name, value.replace('\\', r"\\").replace('\'', r"\'")),
+        // powershell also takes the typographic single quotes as quotes, each is doubled to escape it
+        ExportShell::Powershell => {
+            let quoted: String = value.chars().flat_map(|c| match c {
+                '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' => vec![c, c],
+                c => vec![c]
+            }).collect();
+            format!("$env:{} = '{}'", name, quoted)
+        }
+    }
+}
+
+/// Runs a command with the variables added to its environment. On unix kn is replaced by the command,
+/// elsewhere kn waits for it and exits with its exit code. Returns only if the command cannot be started
+pub fn exec(command: &[String], vars: &[(String, String)]) -> Result<(), Box<dyn Error>> {
+    let (program, args) = match command.split_first() {
+        Some((program, args)) => (program, args),
+        None => return Err(Box::new(KeynoteError::new(ErrorKind::InvalidInput, "no command given to run")))
+    };
+    let mut child = Command::new(program);
+    child.args(args).envs(vars.iter().map(|(name, value)| (name, value)));
+
+    let unable = |e: std::io::Error| {
+        let kind = if e.kind() == std::io::ErrorKind::NotFound { ErrorKind::NotFound } else { ErrorKind::Other };
+        Box::new(KeynoteError::new(kind, format!("unable to run '{}': {}", program, e)))
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(unable(child.exec()))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = child.status().map_err(unable)?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_quoted_for_each_shell() {
+        let value = r"it's a \path";
+        assert_eq!(export_line(ExportShell::Sh, "NOTE", value), r"export NOTE='it'\''s a \path'");
+        assert_eq!(export_line(ExportShell::Fish, "NOTE", value), r"set -gx NOTE 'it\'s a \\path'");
+        assert_eq!(export_line(ExportShell::Powershell, "NOTE", "it's ‘$HOME’"), "$env:NOTE = 'it''s ‘‘$HOME’’'");
+    }
+}
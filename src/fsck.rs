@@ -0,0 +1,410 @@
+use std::{fmt, fs, str, io::Write, error::Error, collections::{HashMap, HashSet}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{parse::{parse_line, Line}, temp_filepath, legacy_temp_filepath, Section, KeynoteFile, is_encrypted, error::{invalid_input, not_found}, permissions::{copy_permissions, create_private_file},
+            recovery::lock_writes};
+
+/// name of the section that entries found before any section header are moved into by a repair.
+/// If the file already has a section of that name, a number is added, e.g. `orphaned_2`
+pub const ORPHAN_SECTION_NAME: &str = "orphaned";
+
+/// A problem found in a data file by `fsck`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// a temp file from an interrupted rewrite was left next to the data file
+    LeftoverTempFile(PathBuf),
+    /// an entry appears before any section header
+    OrphanEntry { line: usize, key: String },
+    /// a key is defined more than once. a repair renames the later one
+    DuplicateKey { line: usize, key: String, first_line: usize, renamed_to: String },
+    /// a section header appears more than once. a repair merges the entries into the first
+    DuplicateSection { line: usize, name: String },
+    /// an entry is missing its `<~>` terminator. a repair keeps the value as found
+    UnterminatedEntry { line: usize, key: String },
+    /// a line that cannot be recovered. a repair drops it, it is kept in the backup
+    Malformed { line: usize, column: usize, reason: String }
+}
+
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsckProblem::LeftoverTempFile(path) =>
+                write!(f, "leftover temp file from an interrupted write: {}", path.display()),
+            FsckProblem::OrphanEntry { line, key } =>
+                write!(f, "line {}: entry '{}' appears before any section header", line, key),
+            FsckProblem::DuplicateKey { line, key, first_line, renamed_to } =>
+                write!(f, "line {}: key '{}' already defined on line {} (repair renames it to '{}')", line, key, first_line, renamed_to),
+            FsckProblem::DuplicateSection { line, name } =>
+                write!(f, "line {}: section '{}' is defined more than once", line, name),
+            FsckProblem::UnterminatedEntry { line, key } =>
+                write!(f, "line {}: entry '{}' is missing its '<~>' terminator", line, key),
+            FsckProblem::Malformed { line, column, reason } =>
+                write!(f, "line {}, column {}: {}", line, column, reason)
+        }
+    }
+}
+
+/// The result of checking a data file with `fsck`
+#[derive(Debug)]
+pub struct FsckReport {
+    /// path to the checked data file
+    pub filepath: PathBuf,
+    /// problems found, in the order they were found
+    pub problems: Vec<FsckProblem>,
+    /// repaired file contents, sections in the order they first appear
+    repaired: Vec<Section>
+}
+
+impl FsckReport {
+    /// Returns true when no problems were found
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Rewrites the data file with the problems repaired and removes any leftover temp file.
+    /// The file is checked again under the write lock, so the repair is made to the file as it is, not as it was
+    /// when this report was made. The original file is copied to a backup first. Returns the path to the backup,
+    /// or None if the file was clean
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let kn_file = KeynoteFile::new("kntest_fsck_doc.dat").unwrap();
+    /// fs::create_dir_all(kn_file.filepath.parent().unwrap()).unwrap();
+    /// fs::write(&kn_file.filepath, "\t<orphan>value<~>\n<leaders>\n\t<atreides>leto\n").unwrap();
+    ///
+    /// let report = fsck(&kn_file.filepath).unwrap();
+    /// assert_eq!(report.problems.len(), 2);
+    ///
+    /// let backup = report.repair().unwrap().unwrap();
+    /// assert!(fsck(&kn_file.filepath).unwrap().is_clean());
+    ///
+    /// fs::remove_file(backup);                // remove the backup
+    /// fs::remove_file(kn_file.filepath);      // remove the test file
+    /// ```
+    pub fn repair(&self) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        if self.is_clean() {
+            return Ok(None);
+        }
+
+        // the file is checked again under the lock, a write since this report was made would be lost otherwise
+        let _lock = lock_writes(&self.filepath)?;
+        let report = fsck(&self.filepath)?;
+        if report.is_clean() {
+            return Ok(None);
+        }
+
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let filename = match self.filepath.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Err(invalid_input("error: invalid data file path"))
+        };
+        let backup_path = self.filepath.with_file_name(format!("{}.fsck-{}.bak", filename, secs));
+        fs::copy(&self.filepath, &backup_path)?;
+
+        let mut contents = String::new();
+        for section in &report.repaired {
+            contents.push_str(&Section::build_section_string(&section.name));
+            if let Some(metadata_str) = section.metadata.build_metadata_string() {
+                contents.push_str(&metadata_str);
+            }
+            let mut keys: Vec<&String> = section.data.keys().collect();
+            keys.sort();
+            for key in keys {
+                contents.push_str(&KeynoteFile::build_entry_string(key, &section.data[key]));
+                if let Some(metadata_str) = section.entry_metadata.get(key).and_then(|m| m.build_metadata_string()) {
+                    contents.push_str(&metadata_str);
+                }
+            }
+        }
+
+        let tmp_filepath = temp_filepath(&self.filepath);
+        let mut tmp_file = create_private_file(&tmp_filepath)?;
+        tmp_file.write_all(contents.as_bytes())?;
+        tmp_file.sync_all()?;
+        copy_permissions(&self.filepath, &tmp_filepath)?;
+        fs::rename(&tmp_filepath, &self.filepath)?;
+
+        if let Some(legacy_tmp_filepath) = legacy_temp_filepath(&self.filepath).filter(|path| path.exists()) {
+            fs::remove_file(legacy_tmp_filepath)?;
+        }
+
+        Ok(Some(backup_path))
+    }
+}
+
+/// Checks a data file for corruption without modifying it. Reports leftover temp files, orphan entries,
+/// duplicate keys and sections, entries missing their terminator and unrecoverable lines
+///
+/// # Arguments
+///
+/// * `filepath` - path to the data file to check
+///
+/// # Examples    ///
+/// ```
+/// use std::fs;
+/// use keydata::*;
+///
+/// let kn_file = KeynoteFile::new("kntest_fsck_check_doc.dat").unwrap();
+/// fs::create_dir_all(kn_file.filepath.parent().unwrap()).unwrap();
+/// fs::write(&kn_file.filepath, "<leaders>\n\t<atreides>leto<~>\n").unwrap();
+///
+/// let report = fsck(&kn_file.filepath).unwrap();
+/// assert!(report.is_clean());
+///
+/// fs::remove_file(kn_file.filepath);  // remove the test file
+/// ```
+pub fn fsck(filepath: &Path) -> Result<FsckReport, Box<dyn Error>> {
+    if !filepath.exists() {
+        return Err(not_found(format!("error: {} does not exist", filepath.display())));
+    }
+    // an encrypted file is authenticated as a whole, so it is either intact or cannot be read at all
+    if is_encrypted(filepath)? {
+        return Err(invalid_input(format!("error: {} is encrypted and cannot be checked. decrypt it first", filepath.display())));
+    }
+
+    let bytes = fs::read(filepath)?;
+    // lines that are not valid UTF-8 are kept as bytes with where they stop being valid, so they are reported
+    // rather than read with replacement characters that a repair would write back
+    let lines: Vec<Result<&str, usize>> = bytes.split(|b| *b == b'\n')
+        .map(|line| str::from_utf8(line.strip_suffix(b"\r").unwrap_or(line)).map_err(|e| e.valid_up_to()))
+        .collect();
+    let mut problems = Vec::new();
+
+    for tmp_filepath in vec![Some(temp_filepath(filepath)), legacy_temp_filepath(filepath)].into_iter().flatten() {
+        if tmp_filepath.exists() {
+            problems.push(FsckProblem::LeftoverTempFile(tmp_filepath));
+        }
+    }
+
+    // names already in the file, so the names a repair makes up never clash with one found later
+    let mut file_sections: HashSet<&str> = HashSet::new();
+    let mut file_keys: HashSet<&str> = HashSet::new();
+    for line in lines.iter().filter_map(|line| line.ok()) {
+        match parse_line(line) {
+            Ok(Line::Section(name)) => { file_sections.insert(name); },
+            Ok(Line::Entry(k, _)) => { file_keys.insert(k); },
+            Err(_) => if let Some((k, _)) = get_unterminated_entry(line) { file_keys.insert(k); },
+            _ => {}
+        }
+    }
+    let orphan_section_name = unused_name(ORPHAN_SECTION_NAME, |name| file_sections.contains(name));
+
+    let mut repaired: Vec<Section> = Vec::new();
+    // every key in the repaired file, as found or as renamed, and the line it came from
+    let mut key_lines: HashMap<String, usize> = HashMap::new();
+    let mut curr_section: Option<usize> = None;
+    // the section, and key if an entry, that a metadata line on the next line belongs to
+    let mut meta_target: Option<(usize, Option<String>)> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let line_number = i + 1;
+        let target = meta_target.take();
+        let line = match line {
+            Ok(line) => *line,
+            Err(valid_up_to) => {
+                problems.push(FsckProblem::Malformed { line: line_number, column: valid_up_to + 1, reason: "line is not valid UTF-8".to_string() });
+                continue;
+            }
+        };
+
+        let (key, value) = match parse_line(line) {
+            Ok(Line::Section(name)) => {
+                match repaired.iter().position(|s| s.name == name) {
+                    Some(index) => {
+                        problems.push(FsckProblem::DuplicateSection { line: line_number, name: name.to_string() });
+                        curr_section = Some(index);
+                    },
+                    None => {
+                        repaired.push(Section::new(name));
+                        curr_section = Some(repaired.len() - 1);
+                        meta_target = Some((repaired.len() - 1, None));
+                    }
+                }
+                continue;
+            },
+            Ok(Line::Entry(k, v)) => (k, v),
+            Ok(Line::Meta(metadata)) => {
+                match target {
+                    Some((index, Some(key))) => { repaired[index].entry_metadata.insert(key, metadata); },
+                    Some((index, None)) => repaired[index].metadata = metadata,
+                    None => problems.push(FsckProblem::Malformed { line: line_number, column: 2, 
+                                            reason: "metadata does not directly follow a section header or entry".to_string() })
+                };
+                continue;
+            },
+            Ok(Line::Blank) => continue,
+            Err((column, reason)) => {
+                match get_unterminated_entry(line) {
+                    Some((k, v)) => {
+                        problems.push(FsckProblem::UnterminatedEntry { line: line_number, key: k.to_string() });
+                        (k, v)
+                    },
+                    None => {
+                        problems.push(FsckProblem::Malformed { line: line_number, column, reason });
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let index = match curr_section {
+            Some(index) => index,
+            None => {
+                problems.push(FsckProblem::OrphanEntry { line: line_number, key: key.to_string() });
+                match repaired.iter().position(|s| s.name == orphan_section_name) {
+                    Some(index) => index,
+                    None => {
+                        repaired.push(Section::new(&orphan_section_name));
+                        repaired.len() - 1
+                    }
+                }
+            }
+        };
+
+        let mut key = key.to_string();
+        if let Some(first_line) = key_lines.get(&key) {
+            let renamed_to = unused_name(&key, |name| key_lines.contains_key(name) || file_keys.contains(name));
+            problems.push(FsckProblem::DuplicateKey { line: line_number, key: key.clone(), first_line: *first_line, renamed_to: renamed_to.clone() });
+            key = renamed_to;
+        }
+
+        key_lines.insert(key.clone(), line_number);
+        repaired[index].add_entry(&key, value);
+        meta_target = Some((index, Some(key)));
+    }
+
+    Ok(FsckReport { filepath: filepath.to_path_buf(), problems, repaired })
+}
+
+// returns name, or name with the first number from 2 up added, e.g. atreides_2, that is not taken
+fn unused_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(name) {
+        return name.to_string();
+    }
+    let mut n = 2;
+    while taken(&format!("{}_{}", name, n)) {
+        n += 1;
+    }
+    format!("{}_{}", name, n)
+}
+
+// recovers key and value from an entry line that was cut off before its terminator
+fn get_unterminated_entry(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("\t<")?;
+    let close = rest.find('>')?;
+    let key = &rest[..close];
+    let value = rest[close + 1..].trim_end_matches(['<', '~']);
+    if key.is_empty() {
+        return None;
+    }
+    Some((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_test_file(filename: &str, contents: &str) -> PathBuf {
+        let kn_file = KeynoteFile::new(filename).unwrap();
+        fs::create_dir_all(kn_file.filepath.parent().unwrap()).unwrap();
+        fs::write(&kn_file.filepath, contents).unwrap();
+        kn_file.filepath
+    }
+
+    #[test]
+    fn get_unterminated_entry_success() {
+        assert_eq!(get_unterminated_entry("\t<atreides>leto<~"), Some(("atreides", "leto")));
+        assert_eq!(get_unterminated_entry("\t<atreides>le"), Some(("atreides", "le")));
+        assert_eq!(get_unterminated_entry("\t<atrei"), None);
+    }
+
+    #[test]
+    fn fsck_finds_duplicates_and_orphans() {
+        let path = write_test_file("kntest_fsck.dat",
+            "\t<orphan>value<~>\n<leaders>\n\t<atreides>leto<~>\n<villains>\n\t<atreides>paul<~>\n<leaders>\n\t<corrino>shaddam<~>\n");
+
+        let report = fsck(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.problems, vec![
+            FsckProblem::OrphanEntry { line: 1, key: "orphan".to_string() },
+            FsckProblem::DuplicateKey { line: 5, key: "atreides".to_string(), first_line: 3, renamed_to: "atreides_2".to_string() },
+            FsckProblem::DuplicateSection { line: 6, name: "leaders".to_string() }
+        ]);
+
+        let names: Vec<&str> = report.repaired.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![ORPHAN_SECTION_NAME, "leaders", "villains"]);
+        assert_eq!(report.repaired[1].data.len(), 2);
+        assert_eq!(report.repaired[2].data.get("atreides_2").unwrap(), "paul");
+    }
+
+    #[test]
+    fn fsck_renames_never_clash_with_names_in_the_file() {
+        let path = write_test_file("kntest_fsck_renames.dat",
+            "\t<stray>value<~>\n<orphaned>\n\t<fremen>stilgar<~>\n<leaders>\n\t<atreides>leto<~>\n\t<atreides>paul<~>\n\t<atreides_2>jessica<~>\n\t<atreides>alia<~>\n");
+
+        let report = fsck(&path).unwrap();
+        let backup = report.repair().unwrap().unwrap();
+        let mut repaired = KeynoteFile::with_path(&path);
+        repaired.load_data().unwrap();
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.problems[1..], [
+            FsckProblem::DuplicateKey { line: 6, key: "atreides".to_string(), first_line: 5, renamed_to: "atreides_3".to_string() },
+            FsckProblem::DuplicateKey { line: 8, key: "atreides".to_string(), first_line: 5, renamed_to: "atreides_4".to_string() }
+        ]);
+        let values: Vec<String> = ["atreides", "atreides_2", "atreides_3", "atreides_4", "stray", "fremen"].iter()
+            .map(|key| repaired.get_value_from_key(key).unwrap_or_default().to_string()).collect();
+        assert_eq!(values, ["leto", "jessica", "paul", "alia", "value", "stilgar"]);
+        // the orphan is kept apart from the section that was already called orphaned
+        assert_eq!(repaired.get_section("orphaned_2").unwrap().data.len(), 1);
+        assert_eq!(repaired.get_section("orphaned").unwrap().data.len(), 1);
+    }
+
+    #[test]
+    fn fsck_reports_lines_that_are_not_utf8() {
+        let kn_file = KeynoteFile::new("kntest_fsck_utf8.dat").unwrap();
+        fs::create_dir_all(kn_file.filepath.parent().unwrap()).unwrap();
+        fs::write(&kn_file.filepath, b"<leaders>\n\t<atreides>le\xfftoo<~>\n\t<corrino>shaddam<~>\n").unwrap();
+
+        let report = fsck(&kn_file.filepath).unwrap();
+        let backup = report.repair().unwrap().unwrap();
+        let repaired = fs::read_to_string(&kn_file.filepath).unwrap();
+        let original = fs::read(&backup).unwrap();
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&kn_file.filepath).unwrap();
+
+        assert_eq!(report.problems, vec![
+            FsckProblem::Malformed { line: 2, column: 14, reason: "line is not valid UTF-8".to_string() }
+        ]);
+        // the line is dropped rather than written back with replacement characters, and kept in the backup
+        assert_eq!(repaired, "<leaders>\n\t<corrino>shaddam<~>\n");
+        assert!(original.windows(2).any(|w| w == b"\xfft"));
+    }
+
+    #[test]
+    fn repair_keeps_changes_made_after_the_check() {
+        let path = write_test_file("kntest_fsck_stale.dat", "<leaders>\n\t<atreides>leto<~>\n\t<atreides>paul<~>\n");
+
+        let report = fsck(&path).unwrap();
+        // another kn process adds an entry after the check
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "\t<corrino>shaddam<~>\n").unwrap();
+        let backup = report.repair().unwrap().unwrap();
+        let mut repaired = KeynoteFile::with_path(&path);
+        repaired.load_data().unwrap();
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(repaired.get_value_from_key("corrino"), Some("shaddam"));
+        assert_eq!(repaired.get_value_from_key("atreides_2"), Some("paul"));
+    }
+
+    #[test]
+    fn fsck_missing_file_is_error() {
+        let kn_file = KeynoteFile::new("kntest_fsck_missing.dat").unwrap();
+        assert!(fsck(&kn_file.filepath).is_err());
+    }
+}
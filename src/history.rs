@@ -0,0 +1,243 @@
+use std::{fmt, error::Error, path::PathBuf};
+
+use crate::{KeynoteFile, is_secret_value, error::{self, not_found}, record::{join_fields, split_fields, unix_now}};
+
+/// Why a prior value of an entry was kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionEvent {
+    /// the entry was given a new value
+    Updated,
+    /// the entry was removed
+    Removed
+}
+
+impl fmt::Display for VersionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionEvent::Updated => f.pad("updated"),
+            VersionEvent::Removed => f.pad("removed")
+        }
+    }
+}
+
+/// A prior value of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryVersion {
+    /// when the value was replaced or removed, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// section the entry was in
+    pub section: String,
+    /// the value the entry held
+    pub value: String,
+    /// what happened to the value
+    pub event: VersionEvent
+}
+
+impl KeynoteFile {
+    /// Turns per-entry version history on or off. While on, the prior value of an entry is kept in a history
+    /// file next to the data file whenever the entry is updated or removed
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - true to keep prior values
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_versioning_doc.dat").unwrap();
+    /// kn_file.set_versioning(true);
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.update_entry("atreides", "paul").unwrap();
+    ///
+    /// let versions = kn_file.history("atreides").unwrap();
+    /// assert_eq!(versions.len(), 1);
+    /// assert_eq!(versions[0].value, "leto");
+    ///
+    /// fs::remove_file(kn_file.history_filepath());    // remove the history
+    /// fs::remove_file(kn_file.filepath);              // remove the test file
+    /// ```
+    pub fn set_versioning(&mut self, enabled: bool) {
+        self.versioning = enabled;
+    }
+
+    /// Returns the path of the history file kept next to the data file
+    pub fn history_filepath(&self) -> PathBuf {
+        let mut path = self.filepath.as_os_str().to_owned();
+        path.push(".history");
+        PathBuf::from(path)
+    }
+
+    /// Returns the prior values of an entry, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::*;
+    ///
+    /// let kn_file = KeynoteFile::new("kntest_history_empty_doc.dat").unwrap();
+    /// assert!(kn_file.history("atreides").unwrap().is_empty());
+    /// ```
+    pub fn history(&self, key: &str) -> Result<Vec<EntryVersion>, Box<dyn Error>> {
+        Ok(read_history(&self.read_text_file(&self.history_filepath())?)?.into_iter().filter(|(k, _)| k == key).map(|(_, v)| v).collect())
+    }
+
+    /// Returns the keys that have prior values kept, sorted
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::*;
+    ///
+    /// let kn_file = KeynoteFile::new("kntest_history_keys_doc.dat").unwrap();
+    /// assert!(kn_file.keys_with_history().unwrap().is_empty());
+    /// ```
+    pub fn keys_with_history(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut keys: Vec<String> = read_history(&self.read_text_file(&self.history_filepath())?)?.into_iter().map(|(k, _)| k).collect();
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Restores a prior value of an entry. If the entry still exists its value is updated, otherwise
+    /// it is added back to the section it was in, adding the section if needed. Returns the restored version
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry
+    /// * `n` - which prior value to restore, 1 is the oldest as returned by `history`
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_restore_version_doc.dat").unwrap();
+    /// kn_file.set_versioning(true);
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.update_entry("atreides", "paul").unwrap();
+    /// kn_file.remove_entry("atreides").unwrap();
+    ///
+    /// kn_file.restore_version("atreides", 1).unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+    ///
+    /// fs::remove_file(kn_file.history_filepath());    // remove the history
+    /// fs::remove_file(kn_file.filepath);              // remove the test file
+    /// ```
+    pub fn restore_version(&mut self, key: &str, n: usize) -> Result<EntryVersion, Box<dyn Error>> {
+        let versions = self.history(key)?;
+        let version = match n.checked_sub(1).and_then(|i| versions.get(i)) {
+            Some(version) => version.clone(),
+            None => return Err(not_found(format!("version {} of '{}' does not exist. {} version(s) available", n, key, versions.len())))
+        };
+
+        if self.contains_key(key) {
+            self.update_entry_stored(key, &version.value)?;
+        }
+        else {
+            if self.get_section(&version.section).is_none() {
+                self.add_section(&version.section)?;
+            }
+            self.add_entry(&version.section, key, &version.value)?;
+        }
+
+        Ok(version)
+    }
+
+    // keeps the prior value of an entry, if versioning is on
+    pub(crate) fn record_version(&self, section: &str, key: &str, value: &str, event: VersionEvent) -> Result<(), Box<dyn Error>> {
+        if !self.versioning {
+            return Ok(());
+        }
+
+        let line = join_fields(&[&unix_now().to_string(), key, section, value, &event.to_string()]);
+        self.append_text_file(&self.history_filepath(), &line)
+    }
+
+    // seals the plain text values kept for the keys, rewriting the versions that held them
+    pub(crate) fn seal_versions(&self, keys: &[String]) -> Result<(), Box<dyn Error>> {
+        let path = self.history_filepath();
+        let contents = self.read_text_file(&path)?;
+
+        let mut sealed = String::new();
+        for line in contents.lines() {
+            let mut fields = split_fields(line);
+            if fields.len() == 5 && keys.contains(&fields[1]) && !is_secret_value(&fields[3]) {
+                fields[3] = self.seal_secret(&fields[1], &fields[3])?;
+                sealed.push_str(&join_fields(&fields.iter().map(|field| field.as_str()).collect::<Vec<&str>>()));
+                continue;
+            }
+            sealed.push_str(line);
+            sealed.push('\n');
+        }
+
+        match sealed == contents {
+            true => Ok(()),
+            false => self.write_text_file(&path, &sealed)
+        }
+    }
+}
+
+// every version in the history file, paired with its key, oldest first
+fn read_history(contents: &str) -> Result<Vec<(String, EntryVersion)>, Box<dyn Error>> {
+    let mut versions = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let corrupt = || error::corrupt(format!("error: history corrupted at line {}", i + 1));
+
+        let mut fields = split_fields(line);
+        if fields.len() != 5 {
+            return Err(corrupt());
+        }
+        let timestamp = fields[0].parse::<u64>().map_err(|_| corrupt())?;
+        let event = match fields[4].as_str() {
+            "updated" => VersionEvent::Updated,
+            "removed" => VersionEvent::Removed,
+            _ => return Err(corrupt())
+        };
+        let value = fields.remove(3);
+        let section = fields.remove(2);
+        let key = fields.remove(1);
+
+        versions.push((key, EntryVersion { timestamp, section, value, event }));
+    }
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn history_tracks_updates_and_removals() {
+        let mut kn_file = KeynoteFile::new("kntest_history.dat").unwrap();
+        kn_file.set_versioning(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        kn_file.update_entry("atreides", "paul").unwrap();
+        kn_file.update_entry("atreides", "leto II").unwrap();
+        kn_file.remove_section("leaders").unwrap();
+
+        let versions = kn_file.history("atreides").unwrap();
+        let values: Vec<&str> = versions.iter().map(|v| v.value.as_str()).collect();
+        assert_eq!(values, vec!["leto", "paul", "leto II"]);
+        assert_eq!(versions[2].event, VersionEvent::Removed);
+        assert_eq!(kn_file.keys_with_history().unwrap(), vec!["atreides", "harkonnen"]);
+
+        // restoring into a removed section adds the section back
+        kn_file.restore_version("atreides", 2).unwrap();
+        kn_file.load_data().unwrap();
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("paul"));
+        assert!(kn_file.restore_version("atreides", 9).is_err());
+
+        fs::remove_file(kn_file.history_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
@@ -0,0 +1,673 @@
+use std::{fmt, fs::File, error::Error, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use crate::{FileItem, KeynoteFile, ErrorKind, KeynoteError, Metadata, Section, error::{self, already_exists, not_found},
+            is_encrypted, record::{join_fields, split_fields, unix_now}, recovery::lock_writes};
+
+/// A mutation made through a KeynoteFile, with enough of the prior state to reverse it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// a section was added, along with the missing parents of a nested section, outermost first
+    AddSection { section: String, parents: Vec<String> },
+    /// an entry was added to a section
+    AddEntry { section: String, key: String, value: String },
+    /// the value of an entry was replaced
+    UpdateEntry { section: String, key: String, old_value: String, value: String },
+    /// an entry was removed, value is what it held before removal
+    RemoveEntry { section: String, key: String, value: String },
+    /// a section was removed along with the entries it held and the sections nested in it, with theirs
+    RemoveSection { section: String, entries: Vec<(String, String)>, nested: Vec<(String, Vec<(String, String)>)> },
+    /// a tag was given to an entry
+    TagEntry { section: String, key: String, tag: String },
+    /// a tag was removed from an entry
+    UntagEntry { section: String, key: String, tag: String },
+    /// an entry was given a new key
+    RenameEntry { section: String, key: String, new_key: String },
+    /// a section, and those nested in it, were given a new name, adding the missing parents of the new name,
+    /// outermost first
+    RenameSection { section: String, new_name: String, parents: Vec<String> },
+    /// the entries of a section were edited as a whole: entries added with their values, entries updated with
+    /// their old and new values, and entries removed with the values they held
+    EditEntries { section: String, added: Vec<(String, String)>, updated: Vec<(String, String, String)>, removed: Vec<(String, String)> },
+    /// an entry was made secret, or plain again. the value is not kept, it is encrypted or decrypted in place
+    SetSecret { section: String, key: String, secret: bool }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::AddSection { section, .. } => write!(f, "added section '{}'", section),
+            Operation::AddEntry { section, key, .. } => write!(f, "added entry '{}' to '{}'", key, section),
+            Operation::UpdateEntry { section, key, .. } => write!(f, "updated entry '{}' in '{}'", key, section),
+            Operation::RemoveEntry { section, key, .. } => write!(f, "removed entry '{}' from '{}'", key, section),
+            Operation::RemoveSection { section, entries, nested } => {
+                write!(f, "removed section '{}' ({} entr{}", section, entries.len(), if entries.len() == 1 { "y" } else { "ies" })?;
+                if !nested.is_empty() {
+                    write!(f, ", {} nested section{}", nested.len(), if nested.len() == 1 { "" } else { "s" })?;
+                }
+                write!(f, ")")
+            },
+            Operation::TagEntry { section, key, tag } => write!(f, "tagged entry '{}' in '{}' with '{}'", key, section, tag),
+            Operation::UntagEntry { section, key, tag } => write!(f, "removed tag '{}' from entry '{}' in '{}'", tag, key, section),
+            Operation::RenameEntry { section, key, new_key } => write!(f, "renamed entry '{}' in '{}' to '{}'", key, section, new_key),
+            Operation::RenameSection { section, new_name, .. } => write!(f, "renamed section '{}' to '{}'", section, new_name),
+            Operation::EditEntries { section, added, updated, removed } => 
+                write!(f, "edited section '{}' ({} added, {} updated, {} removed)", section, added.len(), updated.len(), removed.len()),
+            Operation::SetSecret { section, key, secret: true } => write!(f, "made entry '{}' in '{}' secret", key, section),
+            Operation::SetSecret { section, key, secret: false } => write!(f, "made entry '{}' in '{}' no longer secret", key, section)
+        }
+    }
+}
+
+/// An operation as recorded in the journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalRecord {
+    /// sequence number, increasing with each record
+    pub seq: u64,
+    /// when the operation was made, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// the operation made
+    pub operation: Operation,
+    /// true once the operation has been reversed by `undo`
+    pub undone: bool
+}
+
+impl KeynoteFile {
+    /// Turns the operation journal on or off. While on, each mutation is appended to a journal file next
+    /// to the data file, so it can be listed with `journal` and reversed with `undo`
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - true to record mutations
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_journal_doc.dat").unwrap();
+    /// kn_file.set_journaling(true);
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    ///
+    /// let records = kn_file.journal().unwrap();
+    /// assert_eq!(records.len(), 2);
+    /// assert_eq!(records[1].operation.to_string(), "added entry 'atreides' to 'leaders'");
+    ///
+    /// fs::remove_file(kn_file.journal_filepath());    // remove the journal
+    /// fs::remove_file(kn_file.filepath);              // remove the test file
+    /// ```
+    pub fn set_journaling(&mut self, enabled: bool) {
+        self.journaling = enabled;
+    }
+
+    /// Returns the path of the journal file kept next to the data file
+    pub fn journal_filepath(&self) -> PathBuf {
+        journal_filepath(&self.filepath)
+    }
+
+    /// Returns the operations recorded in the journal, oldest first
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::*;
+    ///
+    /// let kn_file = KeynoteFile::new("kntest_journal_empty_doc.dat").unwrap();
+    /// assert!(kn_file.journal().unwrap().is_empty());
+    /// ```
+    pub fn journal(&self) -> Result<Vec<JournalRecord>, Box<dyn Error>> {
+        read_journal(&self.read_text_file(&self.journal_filepath())?)
+    }
+
+    /// Reverses the last `n` operations in the journal that have not already been undone, newest first.
+    /// Returns the records that were undone
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - number of operations to undo
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_undo_doc.dat").unwrap();
+    /// kn_file.set_journaling(true);
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.remove_section("leaders").unwrap();
+    ///
+    /// kn_file.undo(1).unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+    ///
+    /// fs::remove_file(kn_file.journal_filepath());    // remove the journal
+    /// fs::remove_file(kn_file.filepath);              // remove the test file
+    /// ```
+    pub fn undo(&mut self, n: usize) -> Result<Vec<JournalRecord>, Box<dyn Error>> {
+        let undoable: Vec<JournalRecord> = self.journal()?.into_iter().filter(|r| !r.undone).rev().take(n).collect();
+        if undoable.is_empty() {
+            return Err(not_found("nothing to undo"));
+        }
+
+        // the inverse operations are not journaled themselves, undo records mark what was reversed
+        let journaling = self.journaling;
+        self.journaling = false;
+        let mut undone = Vec::new();
+        for record in undoable {
+            if let Err(e) = self.apply_inverse(&record.operation) {
+                self.journaling = journaling;
+                return Err(Box::new(KeynoteError::new(ErrorKind::of(e.as_ref()), format!("unable to undo #{} ({}): {}", record.seq, record.operation, e))));
+            }
+            self.append_record(&["undo", &record.seq.to_string()])?;
+            undone.push(JournalRecord { undone: true, ..record });
+        }
+        self.journaling = journaling;
+
+        Ok(undone)
+    }
+
+    // appends an operation to the journal, if journaling is on
+    pub(crate) fn record_operation(&self, operation: Operation) -> Result<(), Box<dyn Error>> {
+        if !self.journaling {
+            return Ok(());
+        }
+
+        self.append_record(&operation_fields(&operation))
+    }
+
+    // appends a record numbered one after the last in the journal. the write lock is held from reading the
+    // last number to appending, so kn processes writing at once never give two records the same number
+    fn append_record(&self, fields: &[&str]) -> Result<(), Box<dyn Error>> {
+        let _lock = lock_writes(&self.filepath)?;
+        let path = self.journal_filepath();
+        let timestamp = unix_now().to_string();
+        let record = |last_line: Option<&str>| {
+            let seq = last_line.and_then(|line| line.split('\t').next()?.parse::<u64>().ok()).unwrap_or(0) + 1;
+            let seq = seq.to_string();
+            let mut record: Vec<&str> = vec![&seq, &timestamp];
+            record.extend(fields);
+            join_fields(&record)
+        };
+
+        // an encrypted journal is rewritten whole to append to it, so it is read once for both
+        if self.cipher().is_some() || is_encrypted(&path)? {
+            let mut contents = self.read_text_file(&path)?;
+            let record = record(contents.lines().last());
+            contents.push_str(&record);
+            return self.write_text_file(&path, &contents);
+        }
+        let record = record(last_line(&path)?.as_deref());
+        self.append_text_file(&path, &record)
+    }
+
+    fn apply_inverse(&mut self, operation: &Operation) -> Result<(), Box<dyn Error>> {
+        match operation {
+            // the outermost section added holds the others
+            Operation::AddSection { section, parents } => self.remove_section(parents.first().unwrap_or(section)),
+            Operation::AddEntry { key, .. } => self.remove_entry(key),
+            Operation::UpdateEntry { key, old_value, .. } => self.update_entry_stored(key, old_value),
+            Operation::RemoveEntry { section, key, value } => self.add_entry(section, key, value),
+            Operation::RemoveSection { section, entries, nested } => {
+                let mut sections = vec![(section.clone(), entries.clone())];
+                sections.extend(nested.iter().cloned());
+                self.restore_sections(&sections)
+            },
+            Operation::TagEntry { key, tag, .. } => self.untag(key, tag),
+            Operation::UntagEntry { key, tag, .. } => self.tag(key, tag),
+            Operation::RenameEntry { key, new_key, .. } => self.rename_entry(new_key, key),
+            Operation::RenameSection { section, new_name, parents } => self.move_section(new_name, section, &[], parents),
+            Operation::EditEntries { section, added, updated, removed } => {
+                let mut entries: Vec<(String, String)> = match self.get_section(section) {
+                    Some(section) => section.data.iter().filter(|(key, _)| !added.iter().any(|(k, _)| k == *key))
+                                                        .map(|(key, value)| (key.clone(), value.clone())).collect(),
+                    None => return Err(not_found(format!("section: '{}' does not exist", section)))
+                };
+                for (key, old_value, _) in updated {
+                    if let Some(entry) = entries.iter_mut().find(|(k, _)| k == key) {
+                        entry.1 = old_value.clone();
+                    }
+                }
+                entries.extend(removed.iter().cloned());
+                self.replace_entries(section, &entries).map(|_| ())
+            },
+            Operation::SetSecret { key, secret, .. } => self.set_secret(key, !secret)
+        }
+    }
+
+    // the key of an entry followed by the keys it was renamed from, newest first
+    pub(crate) fn earlier_keys(&self, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut keys = vec![key.to_string()];
+        for record in self.journal()?.into_iter().rev() {
+            if let Operation::RenameEntry { key, new_key, .. } = record.operation {
+                if keys.contains(&new_key) && !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    // seals the plain text values journaled for the keys, rewriting the records that held them
+    pub(crate) fn seal_journaled_values(&self, keys: &[String]) -> Result<(), Box<dyn Error>> {
+        let path = self.journal_filepath();
+        let contents = self.read_text_file(&path)?;
+
+        let mut sealed = String::new();
+        for line in contents.lines() {
+            let fields = split_fields(line);
+            let operation = match fields.len() >= 4 {
+                true => read_operation(&fields[2], &fields[3..]),
+                false => None
+            };
+            match operation {
+                Some(operation) => {
+                    let operation = self.seal_operation(operation, keys)?;
+                    let mut record: Vec<&str> = vec![&fields[0], &fields[1]];
+                    record.extend(operation_fields(&operation));
+                    sealed.push_str(&join_fields(&record));
+                },
+                None => {
+                    sealed.push_str(line);
+                    sealed.push('\n');
+                }
+            }
+        }
+
+        match sealed == contents {
+            true => Ok(()),
+            false => self.write_text_file(&path, &sealed)
+        }
+    }
+
+    // the operation with the plain text values it holds for the keys sealed
+    fn seal_operation(&self, operation: Operation, keys: &[String]) -> Result<Operation, Box<dyn Error>> {
+        let seal = |key: &String, value: String| match keys.contains(key) {
+            true => self.seal_secret(key, &value),
+            false => Ok(value)
+        };
+        let seal_entries = |entries: Vec<(String, String)>| -> Result<Vec<(String, String)>, Box<dyn Error>> {
+            entries.into_iter().map(|(key, value)| Ok((key.clone(), seal(&key, value)?))).collect()
+        };
+
+        Ok(match operation {
+            Operation::AddEntry { section, key, value } => Operation::AddEntry { value: seal(&key, value)?, section, key },
+            Operation::UpdateEntry { section, key, old_value, value } => 
+                Operation::UpdateEntry { old_value: seal(&key, old_value)?, value: seal(&key, value)?, section, key },
+            Operation::RemoveEntry { section, key, value } => Operation::RemoveEntry { value: seal(&key, value)?, section, key },
+            Operation::RemoveSection { section, entries, nested } => {
+                let mut sealed_nested = Vec::new();
+                for (name, entries) in nested {
+                    sealed_nested.push((name, seal_entries(entries)?));
+                }
+                Operation::RemoveSection { section, entries: seal_entries(entries)?, nested: sealed_nested }
+            },
+            Operation::EditEntries { section, added, updated, removed } => {
+                let mut sealed_updated = Vec::new();
+                for (key, old_value, value) in updated {
+                    sealed_updated.push((key.clone(), seal(&key, old_value)?, seal(&key, value)?));
+                }
+                Operation::EditEntries { section, added: seal_entries(added)?, updated: sealed_updated, removed: seal_entries(removed)? }
+            },
+            operation => operation
+        })
+    }
+
+    // adds removed sections back with their entries in a single write, along with any missing parent,
+    // so a failure part way leaves the file as it was
+    fn restore_sections(&mut self, sections: &[(String, Vec<(String, String)>)]) -> Result<(), Box<dyn Error>> {
+        let mut names: Vec<&str> = Vec::new();
+        for (section, entries) in sections {
+            if self.get_section(section).is_some() {
+                return Err(already_exists(format!("section: '{}' already exists", section)));
+            }
+            if let Some((key, _)) = entries.iter().find(|(key, _)| self.contains_key(key)) {
+                return Err(already_exists(format!("key: {} already exists", key)));
+            }
+            let mut parent = Section::parent_path(section);
+            while let Some(name) = parent {
+                if self.get_section(name).is_none() && !names.contains(&name) {
+                    names.push(name);
+                }
+                parent = Section::parent_path(name);
+            }
+            names.push(section);
+        }
+        // parents sort before the sections nested in them
+        names.sort_unstable();
+        names.dedup();
+
+        let mut items = self.read_items()?;
+        let mut restored = Vec::new();
+        for name in names {
+            let metadata = Metadata::created_now();
+            items.push(FileItem { line: Section::build_section_string(name), meta: Some(metadata.clone()) });
+            let entries = sections.iter().find(|(section, _)| section == name).map(|(_, entries)| entries.as_slice()).unwrap_or_default();
+            for (k, v) in entries {
+                items.push(FileItem { line: KeynoteFile::build_entry_string(k, v), meta: Some(metadata.clone()) });
+            }
+            restored.push((name.to_string(), entries, metadata));
+        }
+
+        self.write_items(&items)?;
+
+        for (name, entries, metadata) in restored {
+            let mut section = Section::new(&name);
+            for (k, v) in entries {
+                section.add_entry(k, v);
+                section.entry_metadata.insert(k.clone(), metadata.clone());
+            }
+            section.metadata = metadata;
+            self.sections.insert(name, section);
+        }
+        Ok(())
+    }
+}
+
+// marks, in place of a key, the start of a nested section in a remove_section record. keys cannot hold '<'
+const NESTED_SECTION_FIELD: &str = "<section>";
+// bytes read at a time from the end of the journal to find its last record
+const TAIL_CHUNK_LEN: u64 = 4096;
+// mark each change in an edit_entries record, followed by the key and its values
+const ADDED_ENTRY_FIELD: &str = "<added>";
+const UPDATED_ENTRY_FIELD: &str = "<updated>";
+const REMOVED_ENTRY_FIELD: &str = "<removed>";
+
+// the last line of a file, read back from its end so appending to a long journal stays quick
+fn last_line(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Box::new(e))
+    };
+
+    let mut tail: Vec<u8> = Vec::new();
+    let mut start = file.metadata()?.len();
+    while start > 0 {
+        let chunk_start = start.saturating_sub(TAIL_CHUNK_LEN);
+        let mut chunk = vec![0u8; (start - chunk_start) as usize];
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        start = chunk_start;
+
+        // the last line starts after the newline ending the line before it
+        let line = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(i) = line.iter().rposition(|&b| b == b'\n') {
+            return Ok(Some(String::from_utf8_lossy(&line[i + 1..]).to_string()));
+        }
+    }
+
+    let line = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    Ok(if line.is_empty() { None } else { Some(String::from_utf8_lossy(line).to_string()) })
+}
+
+fn journal_filepath(filepath: &Path) -> PathBuf {
+    let mut path = filepath.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
+fn read_journal(contents: &str) -> Result<Vec<JournalRecord>, Box<dyn Error>> {
+    let mut records: Vec<JournalRecord> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let corrupt = || error::corrupt(format!("error: journal corrupted at line {}", i + 1));
+
+        let fields = split_fields(line);
+        if fields.len() < 4 {
+            return Err(corrupt());
+        }
+        let seq = fields[0].parse::<u64>().map_err(|_| corrupt())?;
+        let timestamp = fields[1].parse::<u64>().map_err(|_| corrupt())?;
+        let args = &fields[3..];
+
+        let operation = match (fields[2].as_str(), args.len()) {
+            ("undo", 1) => {
+                let undone_seq = args[0].parse::<u64>().map_err(|_| corrupt())?;
+                if let Some(record) = records.iter_mut().find(|r| r.seq == undone_seq) {
+                    record.undone = true;
+                }
+                continue;
+            },
+            (name, _) => read_operation(name, args).ok_or_else(corrupt)?
+        };
+
+        records.push(JournalRecord { seq, timestamp, operation, undone: false });
+    }
+
+    Ok(records)
+}
+
+// the fields an operation is journaled as, starting with its name
+fn operation_fields(operation: &Operation) -> Vec<&str> {
+    let mut fields: Vec<&str> = Vec::new();
+    match operation {
+        Operation::AddSection { section, parents } => {
+            fields.extend(["add_section", section.as_str()]);
+            fields.extend(parents.iter().map(|parent| parent.as_str()));
+        },
+        Operation::AddEntry { section, key, value } => fields.extend(["add_entry", section.as_str(), key.as_str(), value.as_str()]),
+        Operation::UpdateEntry { section, key, old_value, value } => 
+            fields.extend(["update_entry", section.as_str(), key.as_str(), old_value.as_str(), value.as_str()]),
+        Operation::RemoveEntry { section, key, value } => fields.extend(["remove_entry", section.as_str(), key.as_str(), value.as_str()]),
+        Operation::RemoveSection { section, entries, nested } => {
+            fields.extend(["remove_section", section.as_str()]);
+            for (k, v) in entries {
+                fields.extend([k.as_str(), v.as_str()]);
+            }
+            for (name, entries) in nested {
+                fields.extend([NESTED_SECTION_FIELD, name.as_str()]);
+                for (k, v) in entries {
+                    fields.extend([k.as_str(), v.as_str()]);
+                }
+            }
+        },
+        Operation::TagEntry { section, key, tag } => fields.extend(["tag_entry", section.as_str(), key.as_str(), tag.as_str()]),
+        Operation::UntagEntry { section, key, tag } => fields.extend(["untag_entry", section.as_str(), key.as_str(), tag.as_str()]),
+        Operation::RenameEntry { section, key, new_key } => fields.extend(["rename_entry", section.as_str(), key.as_str(), new_key.as_str()]),
+        Operation::RenameSection { section, new_name, parents } => {
+            fields.extend(["rename_section", section.as_str(), new_name.as_str()]);
+            fields.extend(parents.iter().map(|parent| parent.as_str()));
+        },
+        Operation::EditEntries { section, added, updated, removed } => {
+            fields.extend(["edit_entries", section.as_str()]);
+            for (k, v) in added {
+                fields.extend([ADDED_ENTRY_FIELD, k.as_str(), v.as_str()]);
+            }
+            for (k, old_v, v) in updated {
+                fields.extend([UPDATED_ENTRY_FIELD, k.as_str(), old_v.as_str(), v.as_str()]);
+            }
+            for (k, v) in removed {
+                fields.extend([REMOVED_ENTRY_FIELD, k.as_str(), v.as_str()]);
+            }
+        },
+        Operation::SetSecret { section, key, secret } => 
+            fields.extend(["set_secret", section.as_str(), key.as_str(), if *secret { "secret" } else { "plain" }])
+    };
+    fields
+}
+
+// reads an operation back from its name and the fields journaled after it
+fn read_operation(name: &str, args: &[String]) -> Option<Operation> {
+    let operation = match (name, args.len()) {
+        ("add_section", len) if len >= 1 => Operation::AddSection { section: args[0].clone(), parents: args[1..].to_vec() },
+        ("add_entry", 3) => Operation::AddEntry { section: args[0].clone(), key: args[1].clone(), value: args[2].clone() },
+        ("update_entry", 4) => Operation::UpdateEntry { section: args[0].clone(), key: args[1].clone(), 
+                                                        old_value: args[2].clone(), value: args[3].clone() },
+        ("remove_entry", 3) => Operation::RemoveEntry { section: args[0].clone(), key: args[1].clone(), value: args[2].clone() },
+        ("remove_section", len) if len >= 1 => read_removed_sections(args)?,
+        ("tag_entry", 3) => Operation::TagEntry { section: args[0].clone(), key: args[1].clone(), tag: args[2].clone() },
+        ("untag_entry", 3) => Operation::UntagEntry { section: args[0].clone(), key: args[1].clone(), tag: args[2].clone() },
+        ("rename_entry", 3) => Operation::RenameEntry { section: args[0].clone(), key: args[1].clone(), new_key: args[2].clone() },
+        ("rename_section", len) if len >= 2 => 
+            Operation::RenameSection { section: args[0].clone(), new_name: args[1].clone(), parents: args[2..].to_vec() },
+        ("edit_entries", len) if len >= 1 => read_edited_entries(args)?,
+        ("set_secret", 3) => match args[2].as_str() {
+            "secret" => Operation::SetSecret { section: args[0].clone(), key: args[1].clone(), secret: true },
+            "plain" => Operation::SetSecret { section: args[0].clone(), key: args[1].clone(), secret: false },
+            _ => return None
+        },
+        _ => return None
+    };
+    Some(operation)
+}
+
+// reads the fields of an edit_entries record: the section, then each change as its marker and the key
+// with its values
+fn read_edited_entries(args: &[String]) -> Option<Operation> {
+    let (mut added, mut updated, mut removed) = (Vec::new(), Vec::new(), Vec::new());
+    let mut fields = args[1..].iter().cloned();
+    while let Some(field) = fields.next() {
+        match field.as_str() {
+            ADDED_ENTRY_FIELD => added.push((fields.next()?, fields.next()?)),
+            UPDATED_ENTRY_FIELD => updated.push((fields.next()?, fields.next()?, fields.next()?)),
+            REMOVED_ENTRY_FIELD => removed.push((fields.next()?, fields.next()?)),
+            _ => return None
+        }
+    }
+    Some(Operation::EditEntries { section: args[0].clone(), added, updated, removed })
+}
+
+// reads the fields of a remove_section record: the section, its keys and values, then each nested section
+// as the marker, its name, and its keys and values
+fn read_removed_sections(args: &[String]) -> Option<Operation> {
+    let mut sections: Vec<(String, Vec<(String, String)>)> = vec![(args[0].clone(), Vec::new())];
+    let mut fields = args[1..].iter();
+    while let Some(field) = fields.next() {
+        if field == NESTED_SECTION_FIELD {
+            sections.push((fields.next()?.clone(), Vec::new()));
+            continue;
+        }
+        let value = fields.next()?;
+        sections.last_mut()?.1.push((field.clone(), value.clone()));
+    }
+
+    let (section, entries) = sections.remove(0);
+    Some(Operation::RemoveSection { section, entries, nested: sections })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn undo_reverses_operations_in_order() {
+        let mut kn_file = KeynoteFile::new("kntest_journal_undo.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        kn_file.remove_entry("atreides").unwrap();
+
+        let undone = kn_file.undo(2).unwrap();
+        assert_eq!(undone.iter().map(|r| r.seq).collect::<Vec<u64>>(), vec![4, 3]);
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+        assert!(!kn_file.contains_key("harkonnen"));
+
+        // reload from disk to be sure the file matches the data structure
+        kn_file.load_data().unwrap();
+        assert_eq!(kn_file.get_value_from_key("atreides"), Some("leto"));
+        assert!(!kn_file.contains_key("harkonnen"));
+
+        let records = kn_file.journal().unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records.iter().filter(|r| r.undone).count(), 2);
+
+        kn_file.undo(5).unwrap();
+        assert!(kn_file.get_sections().is_empty());
+        assert!(kn_file.undo(1).is_err());
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn records_written_at_once_are_numbered_apart() {
+        let kn_file = KeynoteFile::new("kntest_journal_seq.dat").unwrap();
+        let writers: Vec<_> = (0..2).map(|writer| {
+            let mut kn_file = KeynoteFile::with_path(&kn_file.filepath);
+            kn_file.set_journaling(true);
+            std::thread::spawn(move || {
+                for i in 0..25 {
+                    // a value longer than the chunks read from the end of the journal
+                    let operation = Operation::AddEntry { section: "leaders".to_string(), key: format!("key{}_{}", writer, i), value: "x".repeat(5000) };
+                    kn_file.record_operation(operation).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let seqs: Vec<u64> = kn_file.journal().unwrap().iter().map(|record| record.seq).collect();
+        assert_eq!(seqs, (1..=50).collect::<Vec<u64>>());
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+    }
+
+    #[test]
+    fn undo_of_section_removal_is_all_or_nothing() {
+        let mut kn_file = KeynoteFile::new("kntest_journal_undo_section.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_section("fremen").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        kn_file.remove_section("leaders").unwrap();
+
+        // a key of the removed section now belongs to another, so restoring it fails before anything is written
+        kn_file.set_journaling(false);
+        kn_file.add_entry("fremen", "harkonnen", "feyd").unwrap();
+        kn_file.set_journaling(true);
+        let before = fs::read_to_string(&kn_file.filepath).unwrap();
+        assert!(kn_file.undo(1).is_err());
+        assert_eq!(fs::read_to_string(&kn_file.filepath).unwrap(), before);
+        assert!(kn_file.get_section("leaders").is_none());
+
+        kn_file.set_journaling(false);
+        kn_file.remove_entry("harkonnen").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.undo(1).unwrap();
+        kn_file.load_data().unwrap();
+        assert_eq!(kn_file.get_section("leaders").unwrap().data.len(), 2);
+        assert_eq!(kn_file.get_value_from_key("harkonnen"), Some("vladimir"));
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn removed_sections_are_read_back_with_those_nested_in_them() {
+        let mut kn_file = KeynoteFile::new("kntest_journal_nested.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("work/clientA").unwrap();
+        kn_file.add_entry("work", "boss", "leto").unwrap();
+        kn_file.add_entry("work/clientA", "host", "<section>").unwrap();
+        kn_file.remove_section("work").unwrap();
+
+        let records = kn_file.journal().unwrap();
+        assert_eq!(records[0].operation, Operation::AddSection { section: "work/clientA".to_string(), parents: vec!["work".to_string()] });
+        assert_eq!(records[3].operation, Operation::RemoveSection {
+            section: "work".to_string(),
+            entries: vec![("boss".to_string(), "leto".to_string())],
+            nested: vec![("work/clientA".to_string(), vec![("host".to_string(), "<section>".to_string())])]
+        });
+
+        // records written before nested sections were kept together still read
+        let old = read_journal("7\t0\tremove_section\tleaders\tatreides\tleto\n").unwrap();
+        assert_eq!(old[0].operation, Operation::RemoveSection { section: "leaders".to_string(),
+                                                              entries: vec![("atreides".to_string(), "leto".to_string())], nested: Vec::new() });
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn journaling_off_records_nothing() {
+        let mut kn_file = KeynoteFile::new("kntest_journal_off.dat").unwrap();
+        kn_file.add_section("leaders").unwrap();
+
+        assert!(!kn_file.journal_filepath().exists());
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
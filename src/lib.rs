@@ -28,19 +28,70 @@
 //!}
 //! ```
 
-use std::{fs, fs::{OpenOptions, File}, io, io::{Write, prelude::*}, collections::HashMap, path::PathBuf, error::Error};
+use std::{fs, fs::File, io::Write, collections::HashMap, path::{Path, PathBuf}, error::Error};
 
 mod section;
+mod parse;
+mod fsck;
+mod recovery;
+mod backup;
+mod journal;
+mod history;
+mod record;
+mod metadata;
+mod tags;
+mod naming;
+mod encryption;
+mod secret;
+mod permissions;
+mod error;
+mod notebook;
+mod search;
+mod workspace;
+mod rename;
+mod edit;
+mod dryrun;
+mod resolve;
+mod env;
 
 use aoutils::*;
+use permissions::*;
+use error::{already_exists, corrupt, not_found};
 pub use section::*;
+pub use parse::*;
+pub use fsck::*;
+pub use recovery::*;
+pub use backup::*;
+pub use journal::*;
+pub use history::*;
+pub use metadata::*;
+pub use tags::*;
+pub use naming::*;
+pub use encryption::*;
+pub use secret::*;
+pub use permissions::PermissionWarning;
+pub use notebook::*;
+pub use workspace::*;
+pub use edit::*;
+pub use env::*;
+pub use error::{ErrorKind, KeynoteError};
 
 /// A data structure to represent the keynotes data file
 pub struct KeynoteFile {
     /// path to the file as a PathBuf
     pub filepath : PathBuf,
     /// hashmap to store Section instances
-    sections : HashMap<String, Section> 
+    sections : HashMap<String, Section>,
+    /// when set, previous versions of the file are kept before each rewrite
+    backup_policy : Option<BackupPolicy>,
+    /// when true, each mutation is recorded in the operation journal
+    journaling : bool,
+    /// when true, prior values of updated and removed entries are kept
+    versioning : bool,
+    /// when set, the file and the records kept alongside it are encrypted with a key derived from a passphrase
+    cipher : Option<Cipher>,
+    /// when set, values of secret entries can be encrypted and revealed
+    secret_cipher : Option<Cipher>
 }
 
 impl KeynoteFile {
@@ -69,13 +120,37 @@ impl KeynoteFile {
         
         data_filepath.push(format!(".keynotes/{}", filename));
         
-        Ok(KeynoteFile {
+        Ok(KeynoteFile::with_path(&data_filepath))
+    }
+
+    /// Creates a new KeynoteFile for a data file at any path
+    ///
+    /// # Arguments
+    ///
+    /// * `filepath` - path of the data file
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::path::Path;
+    /// use keydata::*;
+    /// let kn_file = KeynoteFile::with_path(Path::new("/tmp/notes/kntest.dat"));
+    /// 
+    /// assert!(kn_file.filepath.ends_with("notes/kntest.dat"));
+    /// ```
+    pub fn with_path(filepath: &Path) -> KeynoteFile {
+        KeynoteFile {
             sections: HashMap::new(),
-            filepath: data_filepath 
-        })
+            filepath: filepath.to_path_buf(),
+            backup_policy: None,
+            journaling: false,
+            versioning: false,
+            cipher: None,
+            secret_cipher: None
+        }
     }
 
-    /// Loads data from file into KeynoteFile structure     
+    /// Loads data from file into KeynoteFile structure. Malformed lines are skipped, use `load_data_with_options`
+    /// with `ParseOptions::strict()` to fail the load on them instead, or to get the skipped lines as warnings
     ///
     /// # Examples
     /// ```
@@ -87,33 +162,169 @@ impl KeynoteFile {
     /// fs::remove_file(file.filepath);  // remove the test file
     /// ```
     pub fn load_data(&mut self) -> Result<(), Box<dyn Error>> {
-        let file = KeynoteFile::open_keynote_file(&self.filepath)?;
-
-        // read lines one at a time, checking for sections and reading them into the data structure
-        let reader = io::BufReader::new(file);         
-        let mut curr_section_name = String::new();
-        for line in reader.lines() {
-            if let Err(_) = line { return Err("error: unable to load data".into()) }
-
-            let line = line.unwrap();            
-            if let Some(section_name) = Section::get_section_name_from_string(&line) {        // handle sections           
-                self.add_section_to_data_structure(section_name);
-                curr_section_name = section_name.to_string();
-            }
-            else if let Some((k, v)) = KeynoteFile::get_entry_from_string(&line) {          // handle entries
-                let section = self.get_section(&curr_section_name);
-                match section {
-                    Some(section) => section.add_entry(k, v), 
-                    None => { 
-                        return Err("error: file format corrupted".into());                            
+        self.load_data_with_options(&ParseOptions::lenient())?;
+        Ok(())
+    }
+
+    /// Loads data from file into KeynoteFile structure, reporting malformed lines with line number, column and reason.
+    /// Temp files left by an interrupted rewrite are cleaned up first and reported in the `LoadReport`.
+    /// In strict mode any malformed line fails the load with a `ParseError` listing every problem.
+    /// In lenient mode malformed lines are skipped and returned as warnings in the `LoadReport`
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - ParseOptions controlling how malformed lines are handled
+    ///
+    /// # Examples
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    /// 
+    /// let mut file = KeynoteFile::new("kntest_parse_doc.dat").unwrap();
+    /// fs::create_dir_all(file.filepath.parent().unwrap()).unwrap();
+    /// fs::write(&file.filepath, "\t<orphan>value<~>\n<leaders>\n\t<atreides>leto<~>\n").unwrap();
+    /// 
+    /// assert!(file.load_data_with_options(&ParseOptions::strict()).is_err());
+    /// assert!(file.load_data().is_ok());    // load_data skips malformed lines
+    /// 
+    /// let report = file.load_data_with_options(&ParseOptions::lenient()).unwrap();
+    /// assert_eq!(report.warnings.len(), 1);
+    /// assert_eq!(report.warnings[0].line, 1);
+    /// assert_eq!(file.get_value_from_key("atreides"), Some("leto"));
+    /// 
+    /// fs::remove_file(file.filepath);  // remove the test file
+    /// ```
+    pub fn load_data_with_options(&mut self, options: &ParseOptions) -> Result<LoadReport, Box<dyn Error>> {
+        // clean up after a rewrite that died part way through, before the data file is opened (and possibly created)
+        let recoveries = recover_interrupted_write(&self.filepath, self.cipher())?;
+
+        KeynoteFile::open_keynote_file(&self.filepath)?;
+        let report = self.load_data_read_only(options)?;
+        Ok(LoadReport { recoveries, ..report })
+    }
+
+    /// Loads data from file into KeynoteFile structure like `load_data_with_options`, without changing anything
+    /// on disk. Temp files left by an interrupted rewrite are left for the next load to clean up, and a data file
+    /// that does not exist loads as empty rather than being created
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - ParseOptions controlling how malformed lines are handled
+    ///
+    /// # Examples
+    /// ```
+    /// use keydata::*;
+    /// 
+    /// let mut file = KeynoteFile::new("kntest_read_only_doc.dat").unwrap();
+    /// file.load_data_read_only(&ParseOptions::lenient()).unwrap();
+    /// assert!(!file.filepath.exists());
+    /// ```
+    pub fn load_data_read_only(&mut self, options: &ParseOptions) -> Result<LoadReport, Box<dyn Error>> {
+        let contents = self.read_text_file(&self.filepath)?;
+
+        // read lines one at a time, checking for sections and reading them into a fresh data structure
+        let mut sections: HashMap<String, Section> = HashMap::new();
+        let mut key_lines: HashMap<String, usize> = HashMap::new();
+        let mut diagnostics = Vec::new();
+        let mut curr_section_name: Option<String> = None;
+        let mut in_malformed_section = false;
+        // the section, and key if an entry, that a metadata line on the next line belongs to
+        let mut meta_target: Option<(String, Option<String>)> = None;
+        // true when the line above was skipped, so its metadata is skipped too
+        let mut skip_meta = false;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line_number = i + 1;
+
+            let parsed = parse_line(line);
+            let target = meta_target.take();
+            let skip = skip_meta;
+            skip_meta = false;
+
+            match parsed {
+                Ok(Line::Section(section_name)) => {                                          // handle sections
+                    if sections.contains_key(section_name) {
+                        diagnostics.push(ParseDiagnostic::new(line_number, 2, 
+                            &format!("duplicate section '{}', its entries are merged into the first", section_name)));
                     }
-                };
-            }                        
+                    else {
+                        sections.insert(section_name.to_string(), Section::new(section_name));
+                    }
+                    curr_section_name = Some(section_name.to_string());
+                    in_malformed_section = false;
+                    meta_target = Some((section_name.to_string(), None));
+                },
+                Ok(Line::Entry(k, v)) => {                                                    // handle entries
+                    let section = match &curr_section_name {
+                        Some(name) => sections.get_mut(name),
+                        None => None
+                    };
+                    match section {
+                        Some(section) => {
+                            if let Some(first_line) = key_lines.get(k) {
+                                diagnostics.push(ParseDiagnostic::new(line_number, 3, 
+                                    &format!("duplicate key '{}', first defined on line {}", k, first_line)));
+                                skip_meta = true;
+                            }
+                            else {
+                                key_lines.insert(k.to_string(), line_number);
+                                section.add_entry(k, v);
+                                meta_target = Some((section.name.clone(), Some(k.to_string())));
+                            }
+                        },
+                        None => {
+                            let reason = if in_malformed_section { "entry follows a malformed section header" } 
+                                         else { "entry appears before any section header" };
+                            diagnostics.push(ParseDiagnostic::new(line_number, 1, reason));
+                            skip_meta = true;
+                        }
+                    };
+                },
+                Ok(Line::Meta(metadata)) => {                                                 // handle metadata
+                    match target {
+                        Some((section_name, key)) => {
+                            if let Some(section) = sections.get_mut(&section_name) {
+                                match key {
+                                    Some(key) => { section.entry_metadata.insert(key, metadata); },
+                                    None => section.metadata = metadata
+                                };
+                            }
+                        },
+                        None if skip => {},
+                        None => diagnostics.push(ParseDiagnostic::new(line_number, 2, 
+                                    "metadata does not directly follow a section header or entry"))
+                    };
+                },
+                Ok(Line::Blank) => {},
+                Err((column, reason)) => {
+                    skip_meta = true;
+                    if line.starts_with('<') {
+                        // entries below a broken header must not be attached to the previous section
+                        curr_section_name = None;
+                        in_malformed_section = true;
+                    }
+                    diagnostics.push(ParseDiagnostic::new(line_number, column, &reason));
+                }
+            }
         }
-        Ok(())
+
+        if options.strict && !diagnostics.is_empty() {
+            return Err(Box::new(ParseError { diagnostics }));
+        }
+
+        self.sections = sections;
+        // notes may hold credentials, so point out when other users can read them
+        let mut permissions = Vec::new();
+        for path in self.filepath.parent().into_iter().chain(Some(self.filepath.as_path())).filter(|path| path.exists()) {
+            if let Some(warning) = check_permissions(path)? {
+                permissions.push(warning);
+            }
+        }
+
+        Ok(LoadReport { warnings: diagnostics, recoveries: Vec::new(), permissions })
     }   
 
-    /// Add a key-value entry into the file
+    /// Add a key-value entry into the file. Keys are checked with `check_key`
     ///
     /// # Arguments
     ///
@@ -133,47 +344,39 @@ impl KeynoteFile {
     ///  
     /// fs::remove_file(kn_file.filepath); // remove the test file
     /// ```
-    pub fn add_entry<'a>(&mut self, section_to_add_to: &str, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    pub fn add_entry(&mut self, section_to_add_to: &str, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        check_key(key)?;
+
         if self.contains_key(key) {
-            return Err(format!("key: {} already exists. no key added.", key).into());            
+            return Err(already_exists(format!("key: {} already exists. no key added.", key)));            
         }      
         
+        if self.get_section(section_to_add_to).is_none() {
+            return Err(not_found(format!("cannot add to '{}'. that section does not exist", section_to_add_to)));
+        }
+
+        // add the new entry to the file, directly below its section header
+        let mut items = self.read_items()?;
+        let header = match KeynoteFile::find_section_item(&items, section_to_add_to) {
+            Some(header) => header,
+            None => return Err(corrupt("error: file corrupted"))
+        };
+
+        let metadata = Metadata::created_now();
+        items.insert(header + 1, FileItem { line: KeynoteFile::build_entry_string(key, value), meta: Some(metadata.clone()) });
+        let section_metadata = KeynoteFile::touch_item(&mut items[header]);
+
+        self.write_items(&items)?;
+
         // insert into data structure
-        if let Some(section) = self.get_section(section_to_add_to){
+        if let Some(section) = self.get_section(section_to_add_to) {
             section.add_entry(key, value);
-        }
-        else {
-            return Err(format!("cannot add to '{}'. that section does not exist", section_to_add_to).into());
-            
+            section.entry_metadata.insert(key.to_string(), metadata);
+            section.metadata = section_metadata;
         }
 
-        // write the new key to the file        
-        let file = KeynoteFile::open_keynote_file(&self.filepath)?;
-        let reader = io::BufReader::new(file);
-            
-        let tmp_filepath = self.filepath.with_file_name("_kntemp.dat");
-        let mut tmp_file = KeynoteFile::open_keynote_file(&tmp_filepath)?;      
-
-        for line in reader.lines() {
-            let line = line.unwrap();                
-            let line = ensure_newline(&line);             
-
-            tmp_file.write_all(line.as_bytes())?;               
-               
-            if let Some(section_name) = Section::get_section_name_from_string(&line) {
-                if section_name == section_to_add_to {
-                    // add new entry to file
-                    let entry = KeynoteFile::build_entry_string(key, value);
-
-                    tmp_file.write_all(entry.as_bytes())?;                            
-                }
-            }
-        }
+        self.record_operation(Operation::AddEntry { section: section_to_add_to.to_string(), key: key.to_string(), value: value.to_string() })?;
 
-        // now we need to delete the old file and rename the temp one
-        fs::remove_file(self.filepath.clone())?;
-        fs::rename(tmp_filepath, self.filepath.clone())?;
-       
         Ok(())
     }
 
@@ -197,107 +400,201 @@ impl KeynoteFile {
     /// fs::remove_file(kn_file.filepath);  // remove the test file  
     /// ```
     pub fn remove_entry(&mut self, key: &str) -> Result<(), Box<dyn Error>>{
-        if !self.contains_key(key) {
-            return Err(format!("key: '{}' does not exist. nothing removed.", key).into());            
-        }     
+        let (removed_from, removed_value) = match self.find_entry(key) {
+            Some(entry) => entry,
+            None => return Err(not_found(format!("key: '{}' does not exist. nothing removed.", key)))
+        };
               
-        let file = KeynoteFile::open_keynote_file(&self.filepath)?;
-        let reader = io::BufReader::new(file);
-            
-        let tmp_filepath = self.filepath.with_file_name("_kntemp.dat");
-        let mut tmp_file = KeynoteFile::open_keynote_file(&tmp_filepath)?;    
-
-        let mut curr_section_name = String::new();
-            
-        for line in reader.lines() {
-            let line = line.unwrap();                
-            let line = ensure_newline(&line);
-
-            if let Some((k, _)) = KeynoteFile::get_entry_from_string(&line) {
-                // line is an entry, only write if it's not the key we're removing
-                if k != key {
-                    tmp_file.write_all(line.as_bytes())?;                             
-                } 
-                else {
-                    // it is the key we're removing... remove from data structure
-                    if let Some(section) = self.get_section(&curr_section_name) {
-                        section.data.remove(key);                            
-                    }
-                }
-            } else {    // line is a section, write for sure
-                let curr_section_opt = Section::get_section_name_from_string(&line);
-                match curr_section_opt {
-                    Some(v) => curr_section_name = v.to_string(),
-                    None => {                           
-                        return Err("error: file corrupted".into());                            
-                    }
-                };
+        let mut items = self.read_items()?;
+        if let Some(index) = KeynoteFile::find_entry_item(&items, key) {
+            items.remove(index);
+        }
+        let section_metadata = match KeynoteFile::find_section_item(&items, &removed_from) {
+            Some(header) => KeynoteFile::touch_item(&mut items[header]),
+            None => Metadata::default()
+        };
+
+        self.write_items(&items)?;
 
-                tmp_file.write_all(line.as_bytes())?;
-            };                                
+        // remove from data structure
+        if let Some(section) = self.get_section(&removed_from) {
+            section.data.remove(key);
+            section.entry_metadata.remove(key);
+            section.metadata = section_metadata;
         }
-            
-        // now we need to delete the old file and rename the temp one
-        fs::remove_file(self.filepath.clone())?;
-        fs::rename(tmp_filepath, self.filepath.clone())?;
+
+        self.record_version(&removed_from, key, &removed_value, VersionEvent::Removed)?;
+        self.record_operation(Operation::RemoveEntry { section: removed_from, key: key.to_string(), value: removed_value })?;
         
         Ok(())
     }
 
-    /// Remove a section from the file
+    /// Replace the value of an existing entry in the file
     ///
     /// # Arguments
     /// 
-    /// * `section_to_remove` - section to remove as string slice  
+    /// * `key` - key for the entry to update as string slice  
+    /// * `value` - new value of the entry as string slice 
     ///
     /// # Examples    ///
     /// ```
     /// use std::fs;
     /// use keydata::*;
     /// 
-    /// let mut kn_file = KeynoteFile::new("kntest.dat").unwrap();    
+    /// let mut kn_file = KeynoteFile::new("kntest_update_doc.dat").unwrap();    
     /// kn_file.add_section("leaders").unwrap();   
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
     /// 
-    /// kn_file.remove_section("leaders");
+    /// kn_file.update_entry("atreides", "paul").unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("atreides"), Some("paul"));
+    /// 
+    /// fs::remove_file(kn_file.filepath);  // remove the test file  
+    /// ```
+    pub fn update_entry(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        // a secret entry stays secret, so its new value is encrypted too
+        if self.is_secret(key) {
+            let sealed = self.seal_secret(key, value)?;
+            return self.update_entry_stored(key, &sealed);
+        }
+        self.update_entry_stored(key, value)
+    }
+
+    // replaces the value of an entry with a value in the form it is stored in
+    pub(crate) fn update_entry_stored(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let (section_name, old_value) = self.replace_entry_value(key, value)?;
+
+        self.record_version(&section_name, key, &old_value, VersionEvent::Updated)?;
+        self.record_operation(Operation::UpdateEntry { section: section_name, key: key.to_string(), 
+                                                       old_value, value: value.to_string() })?;
+
+        Ok(())
+    }
+
+    // writes a stored value in place of the value of an entry, neither versioned nor journaled. returns
+    // the section of the entry and the value replaced
+    pub(crate) fn replace_entry_value(&mut self, key: &str, value: &str) -> Result<(String, String), Box<dyn Error>> {
+        let (section_name, old_value) = match self.find_entry(key) {
+            Some(entry) => entry,
+            None => return Err(not_found(format!("key: '{}' does not exist. nothing updated.", key)))
+        };
+
+        // write the entry with its new value in place of the old one
+        let mut items = self.read_items()?;
+        let index = match KeynoteFile::find_entry_item(&items, key) {
+            Some(index) => index,
+            None => return Err(corrupt("error: file corrupted"))
+        };
+        items[index].line = KeynoteFile::build_entry_string(key, value);
+        let metadata = KeynoteFile::touch_item(&mut items[index]);
+        let section_metadata = match KeynoteFile::find_section_item(&items, &section_name) {
+            Some(header) => KeynoteFile::touch_item(&mut items[header]),
+            None => Metadata::default()
+        };
+
+        self.write_items(&items)?;
+
+        if let Some(section) = self.get_section(&section_name) {
+            section.add_entry(key, value);
+            section.entry_metadata.insert(key.to_string(), metadata);
+            section.metadata = section_metadata;
+        }
+
+        Ok((section_name, old_value))
+    }
+
+    /// Remove a section from the file, along with any sections nested within it. Removing a section
+    /// that does not exist is an error
+    ///
+    /// # Arguments
+    /// 
+    /// * `section_to_remove` - section to remove as string slice  
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    /// 
+    /// let mut kn_file = KeynoteFile::new("kntest_remove_section_doc.dat").unwrap();    
+    /// kn_file.add_section("work/clientA").unwrap();   
+    /// 
+    /// kn_file.remove_section("work").unwrap();
+    /// assert!(kn_file.get_sections().is_empty());
+    /// assert!(kn_file.remove_section("work").is_err());
     /// 
     /// fs::remove_file(kn_file.filepath);  // remove the test file  
     /// ```
     pub fn remove_section(&mut self, section_to_remove: &str) -> Result<(), Box<dyn Error>> {    
-        let file = KeynoteFile::open_keynote_file(&self.filepath)?;
-        let reader = io::BufReader::new(file);
-            
-        let tmp_filepath = self.filepath.with_file_name("_kntemp.dat");
-        let mut tmp_file = KeynoteFile::open_keynote_file(&tmp_filepath)?;
-
-        let mut writing = true;
-        for line in reader.lines() {
-            let line = line.unwrap();               
-            let line = ensure_newline(&line);
-
-            let section_name = Section::get_section_name_from_string(&line);
-            if let Some(section_name) = section_name {
-                if section_name == section_to_remove {
-                    writing = false;    // found the section to remove, stop copying
-                    continue;
-                }
-            }
+        if self.get_section(section_to_remove).is_none() {
+            return Err(not_found(format!("section: '{}' does not exist. nothing removed.", section_to_remove)));
+        }
 
-            if writing || (!writing &&  Section::get_section_name_from_string(&line).is_some()){
-                // !writing in here means we just found a new section after skipping the last, start writing again
-                if !writing { writing = true; } 
-                tmp_file.write_all(line.as_bytes())?;                    
+        // nested sections sort after their parent, so reversing removes children before parents
+        let mut to_remove: Vec<String> = self.sections.keys().filter(|name| Section::is_within(name, section_to_remove))
+                                                             .cloned().collect();
+        to_remove.sort();
+        to_remove.reverse();
+
+        // remove each header and everything below it, up to the next section
+        let mut items = self.read_items()?;
+        for name in &to_remove {
+            if let Some(header) = KeynoteFile::find_section_item(&items, name) {
+                let end = items[header + 1..].iter().position(|item| KeynoteFile::section_name_of_item(item).is_some())
+                                                .map(|i| header + 1 + i)
+                                                .unwrap_or(items.len());
+                items.drain(header..end);
             }
         }
 
-        // now we need to delete the old file and rename the temp one
-        fs::remove_file(self.filepath.clone())?;
-        fs::rename(tmp_filepath, self.filepath.clone())?;        
+        self.write_items(&items)?;
         
-        // remove from data structure
-        self.sections.remove(section_to_remove);
+        // remove from data structure, journaling the section and those nested in it as one operation,
+        // so a single undo restores them all
+        let mut removed: Vec<(String, Vec<(String, String)>)> = Vec::new();
+        for name in to_remove.into_iter().rev() {
+            if let Some(section) = self.sections.remove(&name) {
+                let mut entries: Vec<(String, String)> = section.data.into_iter().collect();
+                entries.sort();
+                for (k, v) in &entries {
+                    self.record_version(&name, k, v, VersionEvent::Removed)?;
+                }
+                removed.push((name, entries));
+            }
+        }
+        if !removed.is_empty() {
+            let (section, entries) = removed.remove(0);
+            self.record_operation(Operation::RemoveSection { section, entries, nested: removed })?;
+        }
 
         Ok(())
     }
+
+    /// Returns the sections nested below a section at any depth, sorted by path
+    ///
+    /// # Arguments
+    /// 
+    /// * `section_name` - path of the section to list below
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    /// 
+    /// let mut kn_file = KeynoteFile::new("kntest_subsections_doc.dat").unwrap();    
+    /// kn_file.add_section("work/clientA/servers").unwrap();   
+    /// kn_file.add_section("work/clientB").unwrap();   
+    /// 
+    /// let names: Vec<&str> = kn_file.subsections("work").iter().map(|s| s.name.as_str()).collect();
+    /// assert_eq!(names, vec!["work/clientA", "work/clientA/servers", "work/clientB"]);
+    /// 
+    /// fs::remove_file(kn_file.filepath);  // remove the test file  
+    /// ```
+    pub fn subsections(&self, section_name: &str) -> Vec<&Section> {
+        let mut subsections: Vec<&Section> = self.sections.values()
+            .filter(|section| section.name != section_name && Section::is_within(&section.name, section_name))
+            .collect();
+        subsections.sort_by(|a, b| a.name.cmp(&b.name));
+        subsections
+    }
     
     /// Returns a reference to this files sections hashmap    
     ///
@@ -314,13 +611,14 @@ impl KeynoteFile {
     /// fs::remove_file(kn_file.filepath);  // remove the test file  
     /// ```
     pub fn get_sections(&self) -> &HashMap<String, Section> {
-        return &self.sections;
+        &self.sections
     }
 
-    /// Adds a new section to the file   
+    /// Adds a new section to the file. A path such as `work/clientA` adds a section nested in another,
+    /// adding any sections along the path that do not exist yet. Names are checked with `check_section_name`
     /// # Arguments
     /// 
-    /// * `section_name` - name of the section to add
+    /// * `section_name` - name or path of the section to add
     /// 
     /// # Examples    
     /// ```
@@ -331,25 +629,48 @@ impl KeynoteFile {
     ///    
     /// kn_file.add_section("leaders").unwrap();   
     /// kn_file.add_section("villains").unwrap();  
+    /// kn_file.add_section("villains/harkonnen").unwrap();  
     ///     
     /// fs::remove_file(kn_file.filepath);  // remove the test file 
     /// ```
     pub fn add_section(&mut self, section_name : &str) -> Result<(), Box<dyn Error>> {       
-        if !is_alphabetic(section_name) {
-            return Err(format!("'{}' is not a valid section name", section_name).into());            
-        }   
+        check_section_name(section_name)?;
 
-        if let Some(_) = self.get_section(section_name) {
-            return Err("section already exists".into());            
+        if self.get_section(section_name).is_some() {
+            return Err(already_exists("section already exists"));            
         }        
-        
-        self.add_section_to_data_structure(section_name);
-    
-        let section_header_str = Section::build_section_string(section_name);
-        let mut file = KeynoteFile::open_keynote_file(&self.filepath)?;
 
-        // write the section header
-        file.write(section_header_str.as_bytes())?;        
+        // missing parents of a nested section are added with it, outermost first
+        let mut parents: Vec<String> = Vec::new();
+        let mut parent = Section::parent_path(section_name);
+        while let Some(name) = parent {
+            if self.get_section(name).is_none() {
+                parents.insert(0, name.to_string());
+            }
+            parent = Section::parent_path(name);
+        }
+
+        let metadata = Metadata::created_now();
+        let mut section_header_str = String::new();
+        for name in parents.iter().map(|parent| parent.as_str()).chain(Some(section_name)) {
+            section_header_str.push_str(&Section::build_section_string(name));
+            if let Some(metadata_str) = metadata.build_metadata_string() {
+                section_header_str.push_str(&metadata_str);
+            }
+        }
+        KeynoteFile::open_keynote_file(&self.filepath)?;
+
+        // write the section headers
+        self.append_text_file(&self.filepath, &section_header_str)?;
+
+        for name in parents.iter().map(|parent| parent.as_str()).chain(Some(section_name)) {
+            self.add_section_to_data_structure(name);
+            if let Some(section) = self.get_section(name) {
+                section.metadata = metadata.clone();
+            }
+        }
+
+        self.record_operation(Operation::AddSection { section: section_name.to_string(), parents })?;
 
         Ok(())
     }  
@@ -376,7 +697,7 @@ impl KeynoteFile {
     /// fs::remove_file(kn_file.filepath);  // remove the test file     
     /// ```
     pub fn get_value_from_key(&mut self, key: &str) -> Option<&str>{           
-        for (_, section) in &self.sections {
+        for section in self.sections.values() {
             if let Some(value) = section.data.get(key) {
                 return Some(value)
             }
@@ -405,12 +726,12 @@ impl KeynoteFile {
     /// fs::remove_file(kn_file.filepath);  // remove the test file     
     /// ```
     pub fn contains_key(&mut self, key: &str) -> bool {           
-        for (_, section) in &self.sections {
+        for section in self.sections.values() {
             if section.data.contains_key(key) {
                 return true;
             }
         }
-        return false
+        false
     }
 
     /// Returns a Section from the file based on section name   
@@ -440,22 +761,119 @@ impl KeynoteFile {
     }
 
     // ---------------------------------------------------- private functions
-    fn open_keynote_file(filepath : &PathBuf) -> Result<File, Box<dyn Error>>{
+    fn open_keynote_file(filepath : &Path) -> Result<File, Box<dyn Error>>{
         // obtain the path to the path_buf parent folder
-        let mut folder = filepath.clone();
+        let mut folder = filepath.to_path_buf();
         folder.pop();        
   
         // if folder doesn't exist, create it
         if !folder.exists() {
-            fs::create_dir(folder)?;
+            create_private_dir(&folder)?;
         }   
 
         // open file as append and read, and return
-        let file = OpenOptions::new().append(true).read(true).create(true).open(filepath.as_path())?;     
+        let file = private_open_options().append(true).read(true).create(true).open(filepath)?;     
      
         Ok(file)       
     }
 
+    // returns the name of the section holding key, and its value
+    fn find_entry(&self, key: &str) -> Option<(String, String)> {
+        for section in self.sections.values() {
+            if let Some(value) = section.data.get(key) {
+                return Some((section.name.clone(), value.clone()));
+            }
+        }
+        None
+    }
+
+    // reads the data file as items, each line paired with the metadata line below it
+    fn read_items(&self) -> Result<Vec<FileItem>, Box<dyn Error>> {
+        KeynoteFile::open_keynote_file(&self.filepath)?;
+        let contents = self.read_text_file(&self.filepath)?;
+
+        let mut items: Vec<FileItem> = Vec::new();
+        for line in contents.lines() {
+            let line = ensure_newline(line);
+
+            if let Ok(Line::Meta(metadata)) = parse_line(&line) {
+                if let Some(item) = items.last_mut() {
+                    if item.meta.is_none() && matches!(parse_line(&item.line), Ok(Line::Section(_)) | Ok(Line::Entry(_, _))) {
+                        item.meta = Some(metadata);
+                        continue;
+                    }
+                }
+            }
+            // anything else, including lines this version does not understand, is kept as is
+            items.push(FileItem { line, meta: None });
+        }
+
+        Ok(items)
+    }
+
+    // writes items to a temp file, then replaces the data file with it
+    fn write_items(&self, items: &[FileItem]) -> Result<(), Box<dyn Error>> {
+        let mut contents = String::new();
+        for item in items {
+            contents.push_str(&item.line);
+            if let Some(metadata_str) = item.meta.as_ref().and_then(|m| m.build_metadata_string()) {
+                contents.push_str(&metadata_str);
+            }
+        }
+
+        let _lock = lock_writes(&self.filepath)?;
+        let (tmp_filepath, mut tmp_file) = self.create_temp_file()?;
+        tmp_file.write_all(&self.seal_bytes(contents.as_bytes())?)?;
+
+        // now we need to replace the old file with the temp one
+        self.replace_with_temp_file(&tmp_filepath, tmp_file)
+    }
+
+    fn find_section_item(items: &[FileItem], section_name: &str) -> Option<usize> {
+        items.iter().position(|item| KeynoteFile::section_name_of_item(item) == Some(section_name))
+    }
+
+    fn find_entry_item(items: &[FileItem], key: &str) -> Option<usize> {
+        items.iter().position(|item| matches!(KeynoteFile::get_entry_from_string(&item.line), Some((k, _)) if k == key))
+    }
+
+    fn section_name_of_item(item: &FileItem) -> Option<&str> {
+        match parse_line(&item.line) {
+            Ok(Line::Section(name)) => Some(name),
+            _ => None
+        }
+    }
+
+    // sets an item's modified time to now, returning its metadata
+    fn touch_item(item: &mut FileItem) -> Metadata {
+        let metadata = item.meta.get_or_insert_with(Metadata::default);
+        metadata.touch();
+        metadata.clone()
+    }
+
+    // creates an empty temp file to write the new version of the data file into
+    fn create_temp_file(&self) -> Result<(PathBuf, File), Box<dyn Error>> {
+        let tmp_filepath = temp_filepath(&self.filepath);
+        let tmp_file = create_private_file(&tmp_filepath)?;
+
+        Ok((tmp_filepath, tmp_file))
+    }
+
+    // flushes the temp file to disk and renames it over the data file. the rename replaces the old file 
+    // in one step, so an interrupted write leaves either the old or the new version in place
+    fn replace_with_temp_file(&self, tmp_filepath: &Path, tmp_file: File) -> Result<(), Box<dyn Error>> {
+        tmp_file.sync_all()?;
+        drop(tmp_file);
+        copy_permissions(&self.filepath, tmp_filepath)?;
+
+        if let Some(policy) = &self.backup_policy {
+            create_backup(&self.filepath, policy)?;
+        }
+        fs::rename(tmp_filepath, &self.filepath)?;
+
+        Ok(())
+    }
+
     fn build_entry_string(key: &str, value: &str) -> String {
         let mut entry: String = String::from("\t<");
         entry.push_str(key);
@@ -468,14 +886,10 @@ impl KeynoteFile {
     } 
 
     fn get_entry_from_string(line: &str) -> Option<(&str, &str)>{
-        if line.starts_with("\t") {
-            if let Some(i) = line.find(">") {
-                let k = &line[2..i];
-                let v = &line[i+1..line.len()-3];
-                return Some((k, v));
-            }
+        match parse_line(line) {
+            Ok(Line::Entry(k, v)) => Some((k, v)),
+            _ => None
         }
-        None
     }    
     
     fn add_section_to_data_structure(&mut self, section_name: &str) {
@@ -484,6 +898,30 @@ impl KeynoteFile {
     
 }
 
+// a line of the data file, along with the metadata line that follows it
+struct FileItem {
+    line: String,
+    meta: Option<Metadata>
+}
+
+// the data file of older versions, now the default notebook
+const LEGACY_DATA_FILENAME: &str = "keynotes.dat";
+
+// path of the temp file used while rewriting a data file
+fn temp_filepath(filepath: &Path) -> PathBuf {
+    let filename = filepath.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    filepath.with_file_name(format!("_kntemp_{}", filename))
+}
+
+// path of the temp file older versions wrote keynotes.dat through, the only data file they had.
+// it belongs to no other data file
+fn legacy_temp_filepath(filepath: &Path) -> Option<PathBuf> {
+    match filepath.file_name() {
+        Some(name) if name == LEGACY_DATA_FILENAME => Some(filepath.with_file_name("_kntemp.dat")),
+        _ => None
+    }
+}
+
 // ---------------------------------------------------- tests
 #[cfg(test)]
 mod tests {
@@ -539,12 +977,170 @@ mod tests {
         };       
     }
 
+    fn load_test_file(filename: &str, contents: &str, options: &ParseOptions) -> (KeynoteFile, Result<LoadReport, Box<dyn Error>>) {
+        let mut test_file = KeynoteFile::new(filename).unwrap();
+        fs::create_dir_all(test_file.filepath.parent().unwrap()).unwrap();
+        fs::write(&test_file.filepath, contents).unwrap();
+
+        let result = test_file.load_data_with_options(options);
+        fs::remove_file(&test_file.filepath).expect("error: unable to remove test file");
+
+        (test_file, result)
+    }
+
+    #[test]
+    fn load_data_strict_reports_every_malformed_line() {
+        let contents = "\t<orphan>value<~>\n<leaders>\n\t<atreides>leto<~>\nstray text\n\t<harkonnen>vladimir\n";
+        let (test_file, result) = load_test_file("kntest_strict.dat", contents, &ParseOptions::strict());
+
+        let err = result.expect_err("strict load should fail");
+        let err = err.downcast_ref::<ParseError>().expect("error should be a ParseError");
+        let lines: Vec<usize> = err.diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![1, 4, 5]);
+        assert_eq!(err.diagnostics[2].column, 21);
+        assert!(test_file.sections.is_empty());
+    }
+
+    #[test]
+    fn load_data_lenient_recovers_and_warns() {
+        let contents = "<leaders>\n\t<atreides>leto<~>\n<broken\n\t<harkonnen>vladimir<~>\n<leaders>\n\t<atreides>paul<~>\n\t<corrino>shaddam<~>\n";
+        let (mut test_file, result) = load_test_file("kntest_lenient.dat", contents, &ParseOptions::lenient());
+
+        let report = result.unwrap();
+        let lines: Vec<usize> = report.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        assert_eq!(test_file.get_sections().len(), 1);
+        assert_eq!(test_file.get_value_from_key("atreides"), Some("leto"));
+        assert_eq!(test_file.get_value_from_key("corrino"), Some("shaddam"));
+        assert!(!test_file.contains_key("harkonnen"));
+    }
+
+    #[test]
+    fn load_data_read_only_leaves_temp_files_alone() {
+        let mut test_file = KeynoteFile::new("kntest_read_only.dat").unwrap();
+        test_file.add_section("leaders").unwrap();
+        let tmp_filepath = temp_filepath(&test_file.filepath);
+        fs::write(&tmp_filepath, "<leaders>\n").unwrap();
+
+        test_file.load_data_read_only(&ParseOptions::lenient()).unwrap();
+        assert!(test_file.get_section("leaders").is_some());
+        assert!(tmp_filepath.exists());
+
+        // the data file is intact, so the next load discards the temp file
+        let report = test_file.load_data_with_options(&ParseOptions::lenient()).unwrap();
+        assert_eq!(report.recoveries, vec![Recovery::DiscardedTemp(tmp_filepath.clone())]);
+        assert!(!tmp_filepath.exists());
+
+        fs::remove_file(test_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn metadata_written_and_reloaded() {
+        let mut test_file = KeynoteFile::new("kntest_metadata.dat").unwrap();
+        test_file.add_section("leaders").unwrap();
+        test_file.add_entry("leaders", "atreides", "leto").unwrap();
+        test_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        test_file.update_entry("atreides", "paul").unwrap();
+        test_file.remove_entry("harkonnen").unwrap();
+
+        let contents = fs::read_to_string(&test_file.filepath).unwrap();
+        assert_eq!(contents.lines().filter(|line| line.starts_with("\t@")).count(), 2);
+
+        let report = test_file.load_data_with_options(&ParseOptions::strict()).unwrap();
+        assert!(report.warnings.is_empty());
+        let section = test_file.get_section("leaders").unwrap();
+        assert!(section.metadata.created.is_some());
+        assert!(section.entry_metadata.get("atreides").unwrap().modified.is_some());
+        assert!(!section.entry_metadata.contains_key("harkonnen"));
+
+        fs::remove_file(&test_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn load_data_old_format_has_no_metadata() {
+        let contents = "<leaders>\n\t<atreides>leto<~>\n";
+        let (mut test_file, result) = load_test_file("kntest_old_format.dat", contents, &ParseOptions::strict());
+
+        assert!(result.is_ok());
+        let section = test_file.get_section("leaders").unwrap();
+        assert_eq!(section.metadata, Metadata::default());
+        assert!(section.entry_metadata.is_empty());
+    }
+
+    #[test]
+    fn load_data_metadata_without_item_is_reported() {
+        let contents = "\t@created=10\n<leaders>\n\t@created=10\n\t@created=20\n";
+        let (_, result) = load_test_file("kntest_stray_metadata.dat", contents, &ParseOptions::lenient());
+
+        let lines: Vec<usize> = result.unwrap().warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![1, 4]);
+    }
+
+    #[test]
+    fn nested_sections_added_and_removed_recursively() {
+        let mut test_file = KeynoteFile::new("kntest_nested_sections.dat").unwrap();
+        test_file.set_journaling(true);
+        test_file.add_section("work/clientA/servers").unwrap();
+        test_file.add_section("workshop").unwrap();
+        test_file.add_entry("work/clientA/servers", "db", "10.0.0.5").unwrap();
+        test_file.add_entry("work", "boss", "leto").unwrap();
+
+        assert!(test_file.add_section("work/").is_err());
+        assert!(test_file.add_section("work//clientB").is_err());
+        assert!(test_file.add_section("work/clientA").is_err());
+
+        test_file.remove_section("work/clientA").unwrap();
+        test_file.load_data().unwrap();
+        let mut names: Vec<&String> = test_file.get_sections().keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["work", "workshop"]);
+        assert!(!test_file.contains_key("db"));
+
+        // one undo brings back the section along with those nested in it
+        let undone = test_file.undo(1).unwrap();
+        assert_eq!(undone[0].operation.to_string(), "removed section 'work/clientA' (0 entries, 1 nested section)");
+        test_file.load_data().unwrap();
+        assert_eq!(test_file.get_value_from_key("db"), Some("10.0.0.5"));
+        assert!(test_file.get_section("work/clientA").is_some());
+
+        // missing parents are added, and undone, with the section nested in them
+        test_file.add_section("home/garden/shed").unwrap();
+        assert!(test_file.get_section("home/garden").is_some());
+        test_file.undo(1).unwrap();
+        test_file.load_data().unwrap();
+        assert!(test_file.get_section("home").is_none());
+        assert_eq!(test_file.journal().unwrap().iter().filter(|r| !r.undone).count(), 4);
+
+        fs::remove_file(test_file.journal_filepath()).unwrap();
+        fs::remove_file(&test_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn invalid_names_are_rejected_before_writing() {
+        let mut test_file = KeynoteFile::new("kntest_invalid_names.dat").unwrap();
+        test_file.add_section("client_acme").unwrap();
+        let before = fs::read_to_string(&test_file.filepath).unwrap();
+
+        let error = test_file.add_entry("client_acme", "bad>key", "value").unwrap_err();
+        assert!(matches!(error.downcast_ref::<NameError>(), Some(NameError::InvalidChar { ch: '>', position: 4, .. })));
+        assert!(test_file.add_entry("client_acme", "", "value").is_err());
+        assert!(test_file.add_section("q3 planning").is_err());
+        assert_eq!(fs::read_to_string(&test_file.filepath).unwrap(), before);
+
+        fs::remove_file(&test_file.filepath).unwrap();
+    }
+
     #[test]
     fn get_section_success() {
         // setup
         let mut test_file = KeynoteFile {
             filepath : PathBuf::new(), // not used for this test, can leave uninitialized
-            sections : HashMap::new() 
+            sections : HashMap::new(),
+            backup_policy : None,
+            journaling : false,
+            versioning : false,
+            cipher : None,
+            secret_cipher : None
         };
         test_file.sections.insert("test_section".to_string(), Section::new("test_section"));
 
@@ -561,7 +1157,12 @@ mod tests {
         // setup
         let mut test_file = KeynoteFile {
             filepath : PathBuf::new(), // not used for this test, can leave uninitialized
-            sections : HashMap::new() 
+            sections : HashMap::new(),
+            backup_policy : None,
+            journaling : false,
+            versioning : false,
+            cipher : None,
+            secret_cipher : None
         };
 
         // execute
@@ -1,165 +1,932 @@
-use std::{env, error::Error};
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-
-    // fail if no arguments passed, otherwise get option param 
-    let option = args.get(1);    
-    let option = if let None = option {
-        println!("kn usage :    kn -[option]      option is mandatory.  kn -help for valid options");
-        return Ok(())         
-    } else {       
-        option.unwrap()    
-    };
-    
-    // create file struct
-    let mut file = keydata::KeynoteFile::new("keynotes.dat")?;   
-    file.load_data()?; 
-
-    // handle various run modes as delineated by option
-    match option.as_str() {
-        "-as" => {                                    // add section
-            
-            let section_name_opt = args.get(2);
-            if let Some(section_name) = section_name_opt {                
-                if let Err(e) = file.add_section(section_name) {
-                    if e.to_string() == format!("'{}' is not a valid section name", section_name) ||
-                        e.to_string() == "section already exists" {
-                            println!("{}", e.to_string());
-                            return Ok(())
-                    }
-                    else {
-                        return Err(e);
-                    }                    
-                }
-                println!("added section '{}'", section_name);
-            }      
-            else {
-                println!("add section usage:    kn -as [section_name]     'section_name' is mandatory.  see kn -help for details");
+use std::{env, error::Error, io::{self, IsTerminal, Write}, process::ExitCode, time::{Duration, SystemTime}};
+
+use clap::Parser;
+use keydata::{ErrorKind, KeynoteError};
+use serde_json::{json, Value};
+
+mod cli;
+mod complete;
+mod editor;
+mod export;
+mod output;
+mod repl;
+mod tui;
+use cli::*;
+use output::{Output, requested_format};
+
+fn main() -> ExitCode {
+    let args = translate_legacy(env::args().collect());
+    let cli = match Cli::try_parse_from(&args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            // help and usage errors are left to clap, unless a script asked for structured errors
+            let out = Output { format: requested_format(&args), quiet: false };
+            if out.is_text() || !e.use_stderr() {
+                e.exit();
             }
+            // the message is the part of clap's output before the usage line
+            let rendered = e.to_string();
+            let lines: Vec<&str> = rendered.lines().take_while(|line| !line.is_empty()).map(|line| line.trim()).collect();
+            let message = lines.join(" ").trim_start_matches("error: ").to_string();
+            out.error(ErrorKind::InvalidInput, 2, &*error(ErrorKind::InvalidInput, message));
+            return ExitCode::from(2);
+        }
+    };
+    let out = cli.output();
 
-        },
+    // clap exits with 2 on usage errors, other failures exit with the code for their kind
+    match run(cli, out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let kind = ErrorKind::of(e.as_ref());
+            out.error(kind, exit_code(kind), e.as_ref());
+            ExitCode::from(exit_code(kind))
+        }
+    }
+}
+
+// exit codes are listed in the help output, see EXIT_CODES_HELP in cli.rs
+fn exit_code(kind: ErrorKind) -> u8 {
+    match kind {
+        ErrorKind::Other => 1,
+        ErrorKind::InvalidInput => 2,
+        ErrorKind::NotFound => 3,
+        ErrorKind::AlreadyExists => 4,
+        ErrorKind::Corrupt => 5,
+        ErrorKind::Io => 6
+    }
+}
 
-        "-rs" => {
+fn run(cli: Cli, out: Output) -> Result<(), Box<dyn Error>> {
+    let notebooks = keydata::Notebooks::new()?;
+    let notebook = cli.notebook;
 
-            if let Some(section_to_remove) = args.get(2) {                
-                println!("removing {}", section_to_remove);
-                file.remove_section(section_to_remove)?;                
+    // an editor session cut short leaves its temp file behind, which may hold revealed values
+    if let Err(e) = editor::remove_stale_temps(notebooks.folder()) {
+        out.warning(&format!("unable to remove temp files left by the editor: {}", e));
+    }
+
+    if cli.dry_run && cli.command.changes_notebook() {
+        return dry_run(&notebooks, &notebook, cli.command, out);
+    }
+
+    match cli.command {
+        Command::Edit { name, section, reveal } => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            edit_command(&mut file, &passphrase, &name, section, reveal, out)
+        },
+        Command::Env { section, shell } => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            let vars = environment(&mut file, &passphrase, section.as_deref())?;
+            if !out.is_text() {
+                out.records(&["name", "value"], vars.iter().map(|(name, value)| vec![json!(name), json!(value)]).collect());
+                return Ok(())
             }
-            else {
-                println!("remove section usage:    kn -rs [section_name]     'section_name' is mandatory.  see kn -help for details");
-            };
-            
+            for (name, value) in vars {
+                println!("{}", export::export_line(shell, &name, &value));
+            }
+            Ok(())
+        },
+        Command::Exec { section, command } => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            let vars = environment(&mut file, &passphrase, section.as_deref())?;
+            export::exec(&command, &vars)
+        },
+        Command::Repl => {
+            let (file, _) = load_notebook(&notebooks, &notebook, out)?;
+            repl::run(file)
+        },
+        Command::Tui => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            // secret values can be edited when a passphrase is at hand, there is no prompt inside the interface
+            let secret_passphrase = passphrase.or_else(|| env::var("KEYNOTES_PASSPHRASE").ok().filter(|p| !p.is_empty()));
+            file.set_secret_passphrase(secret_passphrase.as_deref());
+            tui::run(file)
+        },
+        Command::Completions { shell } => complete::write_script(shell),
+        // completion must never fail loudly or prompt, so problems just mean no names
+        Command::Complete { words } => {
+            let _ = complete::write_names(&words);
+            Ok(())
+        },
+        Command::Notebook { action } => manage_notebooks(&notebooks, action.unwrap_or(NotebookCommand::List), out),
+        Command::Find { text } => find_in_notebooks(&notebooks, &text, out),
+        Command::Fsck { fix } => check_file(&open_notebook(&notebooks, &notebook)?, fix, out),
+        Command::Section(command) => {
+            let (mut file, _) = load_notebook(&notebooks, &notebook, out)?;
+            section_command(&mut file, command, out)
+        },
+        Command::Entry(command) => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            entry_command(&mut file, &passphrase, command, out)
+        },
+        Command::Tag(command) => {
+            let (mut file, _) = load_notebook(&notebooks, &notebook, out)?;
+            tag_command(&mut file, command, out)
+        },
+        Command::Backup(command) => {
+            let (mut file, _) = load_notebook(&notebooks, &notebook, out)?;
+            backup_command(&mut file, command, out)
         },
-        "-ls" => {
+        Command::Encrypt => {
+            let (mut file, _) = load_notebook(&notebooks, &notebook, out)?;
+            if keydata::is_encrypted(&file.filepath)? {
+                out.message(&format!("{} is already encrypted", file.filepath.display()));
+                return Ok(())
+            }
+            file.encrypt(&get_passphrase(true)?)?;
+            out.message(&format!("encrypted {} along with its journal, history and backups", file.filepath.display()));
+            Ok(())
+        },
+        Command::Decrypt => {
+            let (mut file, _) = load_notebook(&notebooks, &notebook, out)?;
+            if !keydata::is_encrypted(&file.filepath)? {
+                out.message(&format!("{} is not encrypted", file.filepath.display()));
+                return Ok(())
+            }
+            file.decrypt()?;
+            out.message(&format!("decrypted {} along with its journal, history and backups", file.filepath.display()));
+            Ok(())
+        },
+        Command::History => {
+            let (file, _) = load_notebook(&notebooks, &notebook, out)?;
+            let records = file.journal()?;
+            if !out.is_text() {
+                let rows = records.iter().map(|record| vec![json!(record.seq), json!(record.timestamp),
+                                                             json!(record.operation.to_string()), json!(record.undone)]).collect();
+                out.records(&["seq", "timestamp", "operation", "undone"], rows);
+                return Ok(())
+            }
 
-            if file.get_sections().len() == 0 {
-                println!("keynotes data file is empty");
+            if records.is_empty() {
+                println!("no operations recorded");
+            }
+            for record in records {
+                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(record.timestamp);
+                println!("{:>5}  {:<20}{}{}", record.seq, format_age(time), record.operation,
+                                                if record.undone { "  (undone)" } else { "" });
+            }
+            Ok(())
+        },
+        Command::Undo { n } => {
+            let (mut file, passphrase) = load_notebook(&notebooks, &notebook, out)?;
+            unlock_secrets_to_undo(&mut file, &passphrase, n)?;
+            let records = file.undo(n)?;
+            if !out.is_text() {
+                let rows = records.iter().map(|record| vec![json!(record.seq), json!(record.operation.to_string())]).collect();
+                out.records(&["seq", "operation"], rows);
                 return Ok(())
             }
-            for section in file.get_sections() {
-                println!("{}", section.0);
-            }  
 
+            for record in records {
+                println!("undid #{}: {}", record.seq, record.operation);
+            }
+            Ok(())
+        }
+    }
+}
+
+// lists the changes a command would make. commands that are journaled are run on a copy of the notebook,
+// so the changes listed are those the command would make. the rest are checked and described
+fn dry_run(notebooks: &keydata::Notebooks, notebook: &Option<String>, command: Command, out: Output) -> Result<(), Box<dyn Error>> {
+    let changes: Vec<String> = match command {
+        // edit would still open the editor on the real values, in a temp file in the data folder
+        Command::Repl | Command::Tui | Command::Edit { .. } =>
+            return Err(error(ErrorKind::InvalidInput, "--dry-run cannot be used with interactive commands")),
+        Command::Notebook { action: Some(NotebookCommand::Create { name }) } => {
+            keydata::check_notebook_name(&name)?;
+            if notebooks.exists(&name) {
+                return Err(error(ErrorKind::AlreadyExists, format!("notebook '{}' already exists", name)));
+            }
+            vec![format!("created notebook '{}'", name)]
+        },
+        Command::Notebook { action: Some(NotebookCommand::Use { name }) } => {
+            notebooks.open(&name)?;
+            vec![format!("now using notebook '{}'", name)]
+        },
+        Command::Notebook { action: Some(NotebookCommand::Delete { name }) } => {
+            keydata::check_notebook_name(&name)?;
+            if !notebooks.exists(&name) {
+                return Err(error(ErrorKind::NotFound, format!("notebook '{}' does not exist", name)));
+            }
+            vec![format!("deleted notebook '{}' with its journal, history and backups", name)]
+        },
+        Command::Fsck { .. } => {
+            let file = open_notebook(notebooks, notebook)?;
+            match file.filepath.exists() {
+                true => keydata::fsck(&file.filepath)?.problems.iter().map(|problem| format!("repaired {}", problem)).collect(),
+                false => Vec::new()
+            }
+        },
+        Command::Encrypt | Command::Decrypt => {
+            let (file, _) = load_notebook(notebooks, notebook, out)?;
+            let encrypt = matches!(command, Command::Encrypt);
+            match keydata::is_encrypted(&file.filepath)? == encrypt {
+                true => Vec::new(),
+                false => vec![format!("{} {} along with its journal, history and backups",
+                                      if encrypt { "encrypted" } else { "decrypted" }, file.filepath.display())]
+            }
+        },
+        Command::Backup(BackupCommand::Restore { n }) => {
+            let (file, _) = load_notebook(notebooks, notebook, out)?;
+            let backups = file.list_backups()?;
+            match n.checked_sub(1).and_then(|i| backups.get(i)) {
+                Some(backup) => vec![format!("restored backup from {}", format_age(backup.created))],
+                None => return Err(error(ErrorKind::NotFound, format!("backup {} does not exist. {} backup(s) available", n, backups.len())))
+            }
         },
-        "-ae" => {
+        command => {
+            let (file, passphrase) = load_notebook(notebooks, notebook, out)?;
+            let quiet = Output { quiet: true, ..out };
+            let records = file.dry_run(|copy| match command {
+                Command::Section(SectionCommand::Remove { name, .. }) => section_command(copy, SectionCommand::Remove { name, yes: true }, quiet),
+                Command::Section(command) => section_command(copy, command, quiet),
+                Command::Entry(command) => entry_command(copy, &passphrase, command, quiet),
+                Command::Tag(command) => tag_command(copy, command, quiet),
+                Command::Undo { n } => unlock_secrets_to_undo(copy, &passphrase, n).and_then(|_| copy.undo(n).map(|_| ())),
+                _ => Ok(())
+            })?;
+            records.iter().map(|record| match record.undone {
+                true => format!("undid #{}: {}", record.seq, record.operation),
+                false => record.operation.to_string()
+            }).collect()
+        }
+    };
+
+    if !out.is_text() {
+        out.records(&["change"], changes.iter().map(|change| vec![json!(change)]).collect());
+        return Ok(())
+    }
+    if changes.is_empty() {
+        println!("dry run: nothing would change");
+        return Ok(())
+    }
+    println!("dry run: nothing was changed. the command would have");
+    for change in changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+// returns the file of the named notebook, or the default one, with backups, journaling and versioning on
+fn open_notebook(notebooks: &keydata::Notebooks, notebook: &Option<String>) -> Result<keydata::KeynoteFile, Box<dyn Error>> {
+    let notebook_name = match notebook {
+        Some(name) => name.clone(),
+        None => notebooks.default_notebook()?
+    };
+
+    let mut file = notebooks.open(&notebook_name)?;
+    file.set_backup_policy(Some(keydata::BackupPolicy::default()));
+    file.set_journaling(true);
+    file.set_versioning(true);
+    Ok(file)
+}
+
+// opens and loads a notebook, asking for its passphrase if it is encrypted. returns the passphrase entered
+fn load_notebook(notebooks: &keydata::Notebooks, notebook: &Option<String>, out: Output)
+    -> Result<(keydata::KeynoteFile, Option<String>), Box<dyn Error>> {
+    let mut file = open_notebook(notebooks, notebook)?;
+
+    // an encrypted file cannot be read without its passphrase
+    let mut passphrase: Option<String> = None;
+    if keydata::is_encrypted(&file.filepath)? {
+        let entered = get_passphrase(false)?;
+        file.set_passphrase(Some(&entered));
+        passphrase = Some(entered);
+    }
+
+    let report = file.load_data_with_options(&keydata::ParseOptions::lenient())?;
+    for recovery in &report.recoveries {
+        out.warning(&format!("recovered {}", recovery));
+    }
+    for warning in &report.warnings {
+        out.warning(&format!("skipped {}", warning));
+    }
+    for warning in &report.permissions {
+        out.warning(&warning.to_string());
+    }
+
+    Ok((file, passphrase))
+}
+
+// checks the file before loading it, loading may fail on a corrupted file
+fn check_file(file: &keydata::KeynoteFile, fix: bool, out: Output) -> Result<(), Box<dyn Error>> {
+    if !file.filepath.exists() {
+        if !out.is_text() {
+            out.records(&["problem"], Vec::new());
+            return Ok(())
+        }
+        println!("keynotes data file does not exist. nothing to check");
+        return Ok(())
+    }
+
+    let report = keydata::fsck(&file.filepath)?;
+    let backup = match fix {
+        true => report.repair()?,
+        false => None
+    };
 
-            if args.len() != 5 {
-                println!("add entry usage:    kn -ae [section_to_add_to] [key] [value]       all options mandatory.  see kn -help for details"); 
-                return Ok(())                  
-            }        
+    if !out.is_text() {
+        let rows = report.problems.iter().map(|problem| vec![json!(problem.to_string())]).collect();
+        out.records(&["problem"], rows);
+        return Ok(())
+    }
 
-            if let (Some(section_to_add_to), Some(key), Some(value)) = (args.get(2), args.get(3), args.get(4)) {                
-                println!("adding <{}>  {}  to  {}", key, value, section_to_add_to);
-                if let Err(e) = file.add_entry(section_to_add_to, key, value) {
-                    if  e.to_string() == "key: {} already exists. no entry added." || 
-                        e.to_string() == "cannot add to '{}'. that section does not exist" {
-                        
-                        println!("{}", e.to_string());
+    if report.is_clean() {
+        println!("no problems found in {}", file.filepath.display());
+        return Ok(())
+    }
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    match backup {
+        Some(backup) => println!("repaired {}. original saved to {}", file.filepath.display(), backup.display()),
+        None => println!("{} problem(s) found. run kn fsck --fix to repair", report.problems.len())
+    };
+    Ok(())
+}
+
+fn section_command(file: &mut keydata::KeynoteFile, command: SectionCommand, out: Output) -> Result<(), Box<dyn Error>> {
+    match command {
+        SectionCommand::Add { name } => {
+            file.add_section(&name)?;
+            out.message(&format!("added section '{}'", name));
+        },
+        SectionCommand::Remove { name, yes } => {
+            if !yes && !confirm_section_removal(file, &name)? {
+                out.message("nothing removed");
+                return Ok(())
+            }
+            file.remove_section(&name)?;
+            out.message(&format!("removed {}", name));
+        },
+        SectionCommand::List { section } => {
+            let mut paths: Vec<&str> = match &section {
+                Some(root) => {
+                    if file.get_section(root).is_none() && file.subsections(root).is_empty() {
+                        return Err(error(ErrorKind::NotFound, format!("section '{}' does not exist", root)));
                     }
-                    else {
-                        return Err(e.to_string().into());
+                    let mut paths: Vec<&str> = file.subsections(root).iter().map(|s| s.name.as_str()).collect();
+                    paths.push(root);
+                    paths
+                },
+                None => file.get_sections().keys().map(|name| name.as_str()).collect()
+            };
+            paths.sort();
+
+            if !out.is_text() {
+                let rows = paths.iter().map(|path| {
+                    let entries = file.get_sections().get(*path).map(|s| s.data.len()).unwrap_or(0);
+                    vec![json!(path), json!(entries)]
+                }).collect();
+                out.records(&["section", "entries"], rows);
+                return Ok(())
+            }
+
+            if paths.is_empty() {
+                println!("keynotes data file is empty");
+                return Ok(())
+            }
+
+            // print as a tree, each section indented below the one it is nested in
+            let mut printed: Vec<&str> = Vec::new();
+            for path in paths {
+                // a loaded file may hold a nested section without the sections above it
+                let mut ancestors = vec![path];
+                while let Some(parent) = keydata::Section::parent_path(ancestors[ancestors.len() - 1]) {
+                    ancestors.push(parent);
+                }
+                for ancestor in ancestors.into_iter().rev() {
+                    if !printed.contains(&ancestor) {
+                        let depth = ancestor.matches(keydata::SECTION_SEPARATOR).count();
+                        let name = ancestor.rsplit(keydata::SECTION_SEPARATOR).next().unwrap_or(ancestor);
+                        println!("{}{}", "    ".repeat(depth), name);
+                        printed.push(ancestor);
                     }
-                }                               
+                }
+            }
+        }
+    };
+
+    Ok(())
+}
+
+// asks before removing a section holding entries or nested sections. without a terminal to ask on, -y is needed
+fn confirm_section_removal(file: &keydata::KeynoteFile, name: &str) -> Result<bool, Box<dyn Error>> {
+    let entries: usize = file.get_sections().values().filter(|section| keydata::Section::is_within(&section.name, name))
+                                                      .map(|section| section.data.len()).sum();
+    let nested = file.subsections(name).len();
+    if entries == 0 && nested == 0 {
+        return Ok(true)
+    }
+
+    let mut contents = format!("{} entr{}", entries, if entries == 1 { "y" } else { "ies" });
+    if nested > 0 {
+        contents.push_str(&format!(" and {} nested section{}", nested, if nested == 1 { "" } else { "s" }));
+    }
+    if !io::stdin().is_terminal() {
+        return Err(error(ErrorKind::InvalidInput, format!("section {} holds {}. pass -y to remove it", name, contents)));
+    }
+
+    eprint!("remove section {} with {}? [y/N] ", name, contents);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn entry_command(file: &mut keydata::KeynoteFile, passphrase: &Option<String>, command: EntryCommand, out: Output) -> Result<(), Box<dyn Error>> {
+    match command {
+        EntryCommand::Add { section, key, value, secret } => {
+            let value = value.join(" ");
+            if secret {
+                unlock_secrets(file, passphrase, true)?;
+                file.add_secret_entry(&section, &key, &value)?;
+                out.message(&format!("added <{}>  {}  to  {}", key, keydata::SECRET_MASK, section));
             }
             else {
-                return Err("parameters not valid. no entry added.".into());
+                file.add_entry(&section, &key, &value)?;
+                out.message(&format!("added <{}>  {}  to  {}", key, value, section));
+            }
+        },
+        EntryCommand::Update { key, value } => {
+            let value = value.join(" ");
+            let shown = if file.is_secret(&key) {
+                unlock_secrets(file, passphrase, false)?;
+                keydata::SECRET_MASK
+            }
+            else {
+                &value
             };
-            
+            file.update_entry(&key, &value)?;
+            out.message(&format!("updated <{}>  to  {}", key, shown));
+        },
+        EntryCommand::Remove { key } => {
+            file.remove_entry(&key)?;
+            out.message(&format!("removed entry with key: {}", key));
         },
-        "-re" => {
+        EntryCommand::Get { key, reveal, resolve } => {
+            if !file.contains_key(&key) {
+                return Err(error(ErrorKind::NotFound, format!("key {} does not exist", key)));
+            }
+            let secret = file.is_secret(&key);
+            let value = if resolve && reveal {
+                // any entry the value refers to may be secret
+                if holds_secrets(file) {
+                    unlock_secrets(file, passphrase, false)?;
+                }
+                file.resolve_revealed(&key)?
+            }
+            else if resolve {
+                file.resolve(&key)?
+            }
+            else if reveal {
+                if secret {
+                    unlock_secrets(file, passphrase, false)?;
+                }
+                file.reveal(&key)?
+            }
+            else {
+                file.get_value_from_key(&key).map(keydata::masked_value).unwrap_or_default().to_string()
+            };
 
-            if args.len() != 3 {                
-                return Err("remove entry usage:    kn -re [key]      key is mandatory.  see kn -help for details".into());                
+            if !out.is_text() {
+                let section = file.get_sections().values().find(|s| s.data.contains_key(&key)).map(|s| s.name.clone());
+                out.record(&["section", "key", "value", "secret"], vec![json!(section), json!(key), json!(value), json!(secret)]);
+            }
+            // piped output is just the value, so it can be passed on without showing on screen
+            else if reveal && !io::stdout().is_terminal() {
+                print!("{}", value);
+                io::stdout().flush()?;
+            }
+            else {
+                println!("{}:   {}", key, value);
             }
-            if let Some(key) = args.get(2) {
-                println!("removing entry with key: {}", key);
-                if let Err(e) = file.remove_entry(key) {
-                    if e.to_string() == "key: '{}' does not exist. nothing removed" {
-                        println!("{}", e.to_string());
+        },
+        EntryCommand::List { since, sort } => {
+            let since = match since {
+                Some(age) => Some(parse_age(&age)?),
+                None => None
+            };
+            let sort_by_age = sort == Some(SortOrder::Age);
+
+            if out.is_text() && since.is_none() && !sort_by_age {
+                for section in file.get_sections().values() {
+                    if !section.data.is_empty() {
+                        println!("{}", section.name)
                     }
-                    else {
-                        return Err(e);
+
+                    for (k, v) in section.data.iter() {
+                        if keydata::is_secret_value(v) {
+                            println!("\t{}    {}", k, keydata::SECRET_MASK);
+                        }
+                        else {
+                            println!("\t{}", k);
+                        }
                     }
-                }                                        
-            }; 
+                }
+                return Ok(())
+            }
 
+            // entries written before metadata was kept have no age, so are left out when filtering or sorting by age
+            // an age reaching back past what the clock can hold takes in every entry
+            let cutoff = since.map(|age| SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH));
+            let mut entries: Vec<(&str, &str, &str, Option<u64>)> = Vec::new();
+            for section in file.get_sections().values() {
+                for (k, v) in section.data.iter() {
+                    let last_changed = section.entry_metadata.get(k).and_then(|m| m.last_changed());
+                    let recent = match (last_changed, cutoff) {
+                        (Some(secs), Some(cutoff)) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs) >= cutoff,
+                        (None, _) => !sort_by_age && cutoff.is_none(),
+                        (Some(_), None) => true
+                    };
+                    if recent {
+                        entries.push((&section.name, k, v, last_changed));
+                    }
+                }
+            }
+            entries.sort();
+            if sort_by_age {
+                entries.sort_by_key(|&(_, _, _, changed)| std::cmp::Reverse(changed));
+            }
+
+            if !out.is_text() {
+                let rows = entries.iter().map(|&(section, key, value, changed)| {
+                    vec![json!(section), json!(key), json!(keydata::masked_value(value)), json!(keydata::is_secret_value(value)), json!(changed)]
+                }).collect();
+                out.records(&["section", "key", "value", "secret", "modified"], rows);
+                return Ok(())
+            }
+
+            for (section, key, _, changed) in entries {
+                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(changed.unwrap_or(0));
+                println!("{:<20}{:<30}{}", section, key, format_age(time));
+            }
+        },
+        EntryCommand::Secret { key } => {
+            unlock_secrets(file, passphrase, true)?;
+            file.set_secret(&key, true)?;
+            out.message(&format!("{} is now secret. its value is encrypted", key));
+        },
+        EntryCommand::Unsecret { key } => {
+            unlock_secrets(file, passphrase, false)?;
+            file.set_secret(&key, false)?;
+            out.message(&format!("{} is no longer secret", key));
         },
-        "-lk" => {
+        EntryCommand::History { key: None, .. } => {
+            let keys = file.keys_with_history()?;
+            if !out.is_text() {
+                out.records(&["key"], keys.iter().map(|key| vec![json!(key)]).collect());
+                return Ok(())
+            }
 
-            for (_, section) in file.get_sections() {   
-                if section.data.len() != 0 {
-                    println!("{}", section.name)
-                }    
-    
-                for (k, _) in section.data.iter() {
-                    println!("\t{}", k);
-                }
+            if keys.is_empty() {
+                println!("no entry history recorded");
+            }
+            for key in keys {
+                println!("{}", key);
+            }
+        },
+        EntryCommand::History { key: Some(key), n: None } => {
+            let versions = file.history(&key)?;
+            if !out.is_text() {
+                let rows = versions.iter().enumerate().map(|(i, version)| {
+                    vec![json!(i + 1), json!(version.timestamp), json!(version.event.to_string()), json!(version.section),
+                         json!(keydata::masked_value(&version.value))]
+                }).collect();
+                out.records(&["n", "timestamp", "event", "section", "value"], rows);
+                return Ok(())
             }
 
+            if versions.is_empty() {
+                println!("no history for key {}", key);
+            }
+            for (i, version) in versions.iter().enumerate() {
+                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(version.timestamp);
+                println!("{:>3}  {:<20}{:<10}{:<16}{}", i + 1, format_age(time), version.event, version.section,
+                                                          keydata::masked_value(&version.value));
+            }
+            if let Some(value) = file.get_value_from_key(&key) {
+                println!("{:>3}  {:<20}{:<10}{:<16}{}", "", "now", "", "", keydata::masked_value(value));
+            }
         },
-        "-lv" => {
+        EntryCommand::History { key: Some(key), n: Some(n) } => {
+            let version = file.restore_version(&key, n)?;
+            out.message(&format!("restored <{}>  to  {}", key, keydata::masked_value(&version.value)));
+        }
+    };
+
+    Ok(())
+}
 
-            if args.len() != 3 {                
-                return Err("list value usage:    kn -lv [key]      key is mandatory.  see kn -help for details".into());                
+// edits the value of a key, or every entry of a section, applying the changes only if all are valid
+fn edit_command(file: &mut keydata::KeynoteFile, passphrase: &Option<String>, name: &str, section: bool, reveal: bool, out: Output)
+    -> Result<(), Box<dyn Error>> {
+    let folder = file.filepath.parent().map(|folder| folder.to_path_buf()).unwrap_or_default();
+
+    // the editor is given a plain text temp file, which must not hold what is kept encrypted unless asked to
+    if passphrase.is_some() && !reveal {
+        return Err(error(ErrorKind::InvalidInput, "the notebook is encrypted, and the editor is given its entries as plain text. \
+                                                   use --reveal to edit it anyway"));
+    }
+
+    if !section && file.contains_key(name) {
+        if file.is_secret(name) {
+            if !reveal {
+                return Err(error(ErrorKind::InvalidInput, format!("{} is secret, and the editor is given its value as plain text. \
+                                                                   use --reveal to edit it anyway", name)));
             }
-            if let Some(key) = args.get(2) {
-                if let Some(value) = file.get_value_from_key(key) {
-                    println!("{}:   {}", key, value);
+            unlock_secrets(file, passphrase, false)?;
+        }
+        let value = file.reveal(name)?;
+        let edited = editor::edit_text(&folder, &format!("{}\n", value))?;
+        let edited = edited.trim_end_matches(['\n', '\r']);
+        if edited.contains('\n') {
+            return Err(error(ErrorKind::InvalidInput, format!("the value of {} must be a single line. nothing changed", name)));
+        }
+        if edited == value {
+            out.message(&format!("{} is unchanged", name));
+            return Ok(())
+        }
+        file.update_entry(name, edited)?;
+        out.message(&format!("updated {}", name));
+        return Ok(())
+    }
+
+    let entries: Vec<(&str, &str)> = match file.get_sections().get(name) {
+        Some(section) => section.data.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
+        None => return Err(error(ErrorKind::NotFound, format!("no key or section named {}", name)))
+    };
+    let text = keydata::entries_to_text(name, &entries);
+    let edited = editor::edit_text(&folder, &text)?;
+    let entries = keydata::entries_from_text(&edited)
+        .map_err(|e| error(ErrorKind::of(e.as_ref()), format!("{}. nothing changed", e)))?;
+
+    // new values of secret entries are encrypted, which needs the passphrase
+    if entries.iter().any(|(k, v)| v != keydata::SECRET_MASK && file.is_secret(k)) {
+        unlock_secrets(file, passphrase, false)?;
+    }
+    let changes = file.replace_entries(name, &entries)?;
+
+    if !out.is_text() {
+        out.record(&["section", "added", "updated", "removed"],
+                   vec![json!(name), json!(changes.added), json!(changes.updated), json!(changes.removed)]);
+        return Ok(())
+    }
+    if changes.is_empty() {
+        out.message(&format!("{} is unchanged", name));
+        return Ok(())
+    }
+    let count = |keys: &[String], what: &str| format!("{} {}{}", what, keys.len(), if keys.is_empty() { String::new() } else { format!(" ({})", keys.join(", ")) });
+    out.message(&format!("{}: {}, {}, {}", name, count(&changes.added, "added"), count(&changes.updated, "updated"), count(&changes.removed, "removed")));
+    Ok(())
+}
+
+fn tag_command(file: &mut keydata::KeynoteFile, command: TagCommand, out: Output) -> Result<(), Box<dyn Error>> {
+    match command {
+        TagCommand::Add { key, tag } => {
+            file.tag(&key, &tag)?;
+            out.message(&format!("tagged {} with '{}'", key, tag));
+        },
+        TagCommand::Remove { key, tag } => {
+            file.untag(&key, &tag)?;
+            out.message(&format!("removed tag '{}' from {}", tag, key));
+        },
+        TagCommand::List { tags, any } => {
+            if tags.is_empty() {
+                let all_tags = file.all_tags();
+                if !out.is_text() {
+                    out.records(&["tag", "entries"], all_tags.iter().map(|(tag, count)| vec![json!(tag), json!(count)]).collect());
+                    return Ok(())
                 }
-                else {
-                    println!("key {} does not exist", key);
-                };
-            };  
-                      
-        },
-
-        // TODO: put the help string into a file that gets loaded
-        _ => {
-                        
-            println!("\n {}", "keynotes v0.1.0:");
-            println!("\n {:>10}\t{}", "legend:",  "[] - mandatory  () - optional");
-            println!("\n {:>10}\t{}", "usage:", "kn [-action] [action params] (optional params)");
-            println!("\n\n {:>12}  {:<20}{:>30}\t{}", "actions:", "-as [section_name]", "add section:", 
-                                                        "adds a section to the file labelled 'section_name'.");
-            println!("{:>140}", "section names must be alphabetical and cannot be duplicated.");
-            println!("\n\n {:>12}  {:<20}{:>30}\t{}", " ", "-rs [section_name]", "remove section:", 
-                                                        "deletes a section from the file if 'section_name' exists.");
-            println!("\n\n {:>12}  {:<30}{:>20}\t{}", " ", "-ls", "list sections:", 
-                                                        "lists all the sections in the file.");                                            
-            println!("\n\n {:>12}  {:<30}{:>18}\t{}", " ", "-ae [section_name] [key] [value]", "add entry:", 
-                                                        "adds an entry to the file in 'section_name'. duplicate keys not allowed.");
-            println!("\n\n {:>12}  {:<30}{:>20}\t{}", " ", "-re [key]", "remove entry:", 
-                                                        "removes an entry from the file if 'key' exists.");
-            println!("\n\n {:>12}  {:<30}{:>20}\t{}", " ", "-lk", "list keys:", 
-                                                        "lists all the keys in the file.");
-            println!("\n\n {:>12}  {:<30}{:>20}\t{}", " ", "-lv", "list value:", 
-                                                        "lists a value from the file if 'key' exists.\n");                                            
-        
-            
-        }        
+
+                if all_tags.is_empty() {
+                    println!("no entries are tagged");
+                }
+                for (tag, count) in all_tags {
+                    println!("{:<20}{} entr{}", tag, count, if count == 1 { "y" } else { "ies" });
+                }
+                return Ok(())
+            }
+
+            let mode = if any { keydata::TagMatch::Any } else { keydata::TagMatch::All };
+            let tags: Vec<&str> = tags.iter().map(|tag| tag.as_str()).collect();
+            let matches = file.entries_with_tags(&tags, mode);
+            if !out.is_text() {
+                out.records(&["section", "key"], matches.iter().map(|(section, key)| vec![json!(section), json!(key)]).collect());
+                return Ok(())
+            }
+
+            if matches.is_empty() {
+                println!("no entries match {}", tags.join(" "));
+            }
+            for (section, key) in matches {
+                println!("{:<20}{}", section, key);
+            }
+        }
+    };
+
+    Ok(())
+}
+
+fn backup_command(file: &mut keydata::KeynoteFile, command: BackupCommand, out: Output) -> Result<(), Box<dyn Error>> {
+    match command {
+        BackupCommand::List => {
+            let backups = file.list_backups()?;
+            if !out.is_text() {
+                let rows = backups.iter().enumerate().map(|(i, backup)| {
+                    vec![json!(i + 1), json!(unix_secs(backup.created)), json!(backup.path.display().to_string())]
+                }).collect();
+                out.records(&["n", "created", "path"], rows);
+                return Ok(())
+            }
+
+            if backups.is_empty() {
+                println!("no backups of the keynotes data file");
+            }
+            for (i, backup) in backups.iter().enumerate() {
+                println!("{:>3}  {:<20}{}", i + 1, format_age(backup.created), backup.path.display());
+            }
+        },
+        BackupCommand::Restore { n } => {
+            let backup = file.restore_backup(n)?;
+            out.message(&format!("restored backup from {}", format_age(backup.created)));
+        }
+    };
+
+    Ok(())
+}
+
+// lists, creates, deletes notebooks and picks the default one
+fn manage_notebooks(notebooks: &keydata::Notebooks, command: NotebookCommand, out: Output) -> Result<(), Box<dyn Error>> {
+    match command {
+        NotebookCommand::List => {
+            let names = notebooks.list()?;
+            let default_name = notebooks.default_notebook()?;
+            if !out.is_text() {
+                out.records(&["notebook", "default"], names.iter().map(|name| vec![json!(name), json!(*name == default_name)]).collect());
+                return Ok(())
+            }
+
+            if names.is_empty() {
+                println!("no notebooks. kn notebook create [name] creates one");
+            }
+            for name in names {
+                let marker = if name == default_name { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+        },
+        NotebookCommand::Create { name } => {
+            notebooks.create(&name)?;
+            out.message(&format!("created notebook '{}'", name));
+        },
+        NotebookCommand::Use { name } => {
+            notebooks.set_default(&name)?;
+            out.message(&format!("now using notebook '{}'", name));
+        },
+        NotebookCommand::Delete { name } => {
+            notebooks.delete(&name)?;
+            out.message(&format!("deleted notebook '{}'", name));
+        }
+    };
+
+    Ok(())
+}
+
+// searches the keys and values of every notebook
+fn find_in_notebooks(notebooks: &keydata::Notebooks, text: &str, out: Output) -> Result<(), Box<dyn Error>> {
+    let mut workspace = keydata::Workspace::new(notebooks.clone())?;
+    let mut any_encrypted = false;
+    for name in workspace.names() {
+        any_encrypted |= keydata::is_encrypted(&notebooks.filepath(name))?;
+    }
+    if any_encrypted {
+        workspace.set_passphrase(Some(&get_passphrase(false)?));
+    }
+
+    let found = workspace.search(text)?;
+    if !out.is_text() {
+        let rows = found.iter().map(|entry| vec![json!(entry.notebook), json!(entry.section), json!(entry.key), json!(entry.value)]).collect();
+        out.records(&["notebook", "section", "key", "value"], rows);
+        return Ok(())
+    }
+
+    if found.is_empty() {
+        println!("no entries match '{}'", text);
+    }
+    for entry in found {
+        println!("{:<20}{:<20}{:<20}{}", entry.notebook, entry.section, entry.key, entry.value);
+    }
+
+    Ok(())
+}
+
+fn unix_secs(time: SystemTime) -> Value {
+    json!(time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+}
+
+// sets the passphrase for secret values, reusing the one the file was decrypted with
+fn unlock_secrets(file: &mut keydata::KeynoteFile, passphrase: &Option<String>, confirm: bool) -> Result<(), Box<dyn Error>> {
+    let secret_passphrase = match passphrase {
+        Some(passphrase) => passphrase.clone(),
+        None => get_passphrase(confirm)?
+    };
+    file.set_secret_passphrase(Some(&secret_passphrase));
+    Ok(())
+}
+
+// returns the entries of a section as environment variables, asking for the secret passphrase if
+// the notebook holds secrets, as values may refer to them
+fn environment(file: &mut keydata::KeynoteFile, passphrase: &Option<String>, section: Option<&str>)
+    -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if holds_secrets(file) {
+        unlock_secrets(file, passphrase, false)?;
+    }
+    file.environment(section)
+}
+
+fn holds_secrets(file: &keydata::KeynoteFile) -> bool {
+    file.get_sections().values().any(|section| section.data.values().any(|value| keydata::is_secret_value(value)))
+}
+
+// undoing an entry made secret, or plain, decrypts or encrypts its value
+fn unlock_secrets_to_undo(file: &mut keydata::KeynoteFile, passphrase: &Option<String>, n: usize) -> Result<(), Box<dyn Error>> {
+    let records = file.journal()?;
+    let mut undoable = records.iter().filter(|record| !record.undone).rev().take(n);
+    match undoable.any(|record| matches!(record.operation, keydata::Operation::SetSecret { .. })) {
+        true => unlock_secrets(file, passphrase, false),
+        false => Ok(())
+    }
+}
+
+fn error(kind: ErrorKind, message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(kind, message))
+}
+
+// reads the passphrase from KEYNOTES_PASSPHRASE, or asks for it without echoing. confirm asks twice
+fn get_passphrase(confirm: bool) -> Result<String, Box<dyn Error>> {
+    if let Ok(passphrase) = env::var("KEYNOTES_PASSPHRASE") {
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+    }
+
+    let prompt = |text: &str| rpassword::prompt_password(text)
+        .map_err(|e| error(ErrorKind::Io, format!("unable to read passphrase ({}). set KEYNOTES_PASSPHRASE instead", e)));
+
+    let passphrase = prompt("passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(error(ErrorKind::InvalidInput, "passphrase cannot be empty"));
+    }
+    if confirm && prompt("confirm passphrase: ")? != passphrase {
+        return Err(error(ErrorKind::InvalidInput, "passphrases do not match"));
+    }
+
+    Ok(passphrase)
+}
+
+// parses an age such as 45s, 30m, 12h, 7d or 2w
+fn parse_age(age: &str) -> Result<Duration, Box<dyn Error>> {
+    let invalid = || error(ErrorKind::InvalidInput, format!("invalid age '{}'. use a number followed by s, m, h, d or w, e.g. 7d", age));
+
+    let split = age.len().saturating_sub(1);
+    let (count, unit) = (age.get(..split).unwrap_or(""), age.get(split..).unwrap_or(""));
+    let count = count.parse::<u64>().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid())
+    };
+
+    Ok(Duration::from_secs(count.checked_mul(unit_secs).ok_or_else(invalid)?))
+}
+
+// formats how long ago a time was, e.g. "5 minutes ago"
+fn format_age(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).map(|age| age.as_secs()).unwrap_or(0);
+    let (count, unit) = match secs {
+        0..=59 => (secs, "second"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day")
     };
 
-    Ok(())  
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
- }
\ No newline at end of file
+    #[test]
+    fn dry_run_of_edit_is_refused_before_the_editor_opens() {
+        let cli = Cli::try_parse_from(["kn", "--dry-run", "edit", "atreides"]).unwrap();
+        let out = cli.output();
+        let e = run(cli, out).unwrap_err();
+        assert_eq!(ErrorKind::of(e.as_ref()), ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "--dry-run cannot be used with interactive commands");
+    }
+}
@@ -0,0 +1,142 @@
+use crate::record::unix_now;
+
+/// Attributes kept for a section or an entry, stored on a line of the form `\t@name=value;name=value`
+/// directly below it in the data file. Files written by older versions have no metadata lines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// when the item was created, in seconds since the unix epoch
+    pub created: Option<u64>,
+    /// when the item was last changed, in seconds since the unix epoch
+    pub modified: Option<u64>,
+    /// tags given to the item, sorted and without duplicates
+    pub tags: Vec<String>,
+    // attributes this version does not know about, kept so rewriting the file does not drop them
+    pub(crate) other: Vec<(String, String)>
+}
+
+impl Metadata {
+    /// Returns Metadata for an item created now
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Metadata;
+    /// let m = Metadata::created_now();
+    /// assert!(m.created.is_some());
+    /// assert_eq!(m.created, m.modified);
+    /// ```
+    pub fn created_now() -> Metadata {
+        let now = unix_now();
+        Metadata { created: Some(now), modified: Some(now), ..Default::default() }
+    }
+
+    /// Sets the modified time to now
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Metadata;
+    /// let mut m = Metadata::default();
+    /// m.touch();
+    /// assert!(m.modified.is_some());
+    /// assert!(m.created.is_none());
+    /// ```
+    pub fn touch(&mut self) {
+        self.modified = Some(unix_now());
+    }
+
+    /// Returns the modified time, or the created time if the item was never modified. None if neither is known
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Metadata;
+    /// let mut m = Metadata::default();
+    /// m.created = Some(10);
+    /// assert_eq!(m.last_changed(), Some(10));
+    /// ```
+    pub fn last_changed(&self) -> Option<u64> {
+        self.modified.or(self.created)
+    }
+
+    /// Formats metadata into the line it appears as in the data file. None if there is nothing to store
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Metadata;
+    /// let mut m = Metadata::default();
+    /// m.created = Some(10);
+    /// m.modified = Some(20);
+    /// assert_eq!(m.build_metadata_string().unwrap(), "\t@created=10;modified=20\n");
+    /// assert!(Metadata::default().build_metadata_string().is_none());
+    /// ```
+    pub fn build_metadata_string(&self) -> Option<String> {
+        let mut fields: Vec<String> = Vec::new();
+        if let Some(created) = self.created {
+            fields.push(format!("created={}", created));
+        }
+        if let Some(modified) = self.modified {
+            fields.push(format!("modified={}", modified));
+        }
+        if !self.tags.is_empty() {
+            fields.push(format!("tags={}", self.tags.join(",")));
+        }
+        for (name, value) in &self.other {
+            fields.push(format!("{}={}", name, value));
+        }
+
+        if fields.is_empty() {
+            return None;
+        }
+        Some(format!("\t@{}\n", fields.join(";")))
+    }
+
+    /// Parses the text following `\t@` on a metadata line, or returns the byte offset and reason it is malformed
+    pub(crate) fn from_fields(text: &str) -> Result<Metadata, (usize, String)> {
+        let mut metadata = Metadata::default();
+        let mut offset = 0;
+        for field in text.split(';') {
+            let (name, value) = match field.split_once('=') {
+                Some((name, value)) if !name.is_empty() => (name, value),
+                _ => return Err((offset, format!("metadata field '{}' is not of the form name=value", field)))
+            };
+
+            let time = || value.parse::<u64>().map_err(|_| (offset + name.len() + 1, format!("'{}' is not a valid time for '{}'", value, name)));
+            match name {
+                "created" => metadata.created = Some(time()?),
+                "modified" => metadata.modified = Some(time()?),
+                "tags" => {
+                    metadata.tags = value.split(',').filter(|tag| !tag.is_empty()).map(|tag| tag.to_string()).collect();
+                    metadata.tags.sort();
+                    metadata.tags.dedup();
+                },
+                _ => metadata.other.push((name.to_string(), value.to_string()))
+            };
+            offset += field.len() + 1;
+        }
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_fields_round_trip() {
+        let metadata = Metadata::from_fields("created=10;modified=20;tags=work,home;future=thing").unwrap();
+        assert_eq!(metadata.created, Some(10));
+        assert_eq!(metadata.modified, Some(20));
+        assert_eq!(metadata.tags, vec!["home", "work"]);
+        assert_eq!(metadata.build_metadata_string().unwrap(), "\t@created=10;modified=20;tags=home,work;future=thing\n");
+    }
+
+    #[test]
+    fn from_fields_invalid_time() {
+        let result = Metadata::from_fields("created=10;modified=soon");
+        assert_eq!(result.unwrap_err().0, 20);
+    }
+
+    #[test]
+    fn from_fields_missing_value() {
+        let result = Metadata::from_fields("created");
+        assert_eq!(result.unwrap_err().0, 0);
+    }
+}
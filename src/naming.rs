@@ -0,0 +1,179 @@
+//! Naming rules for sections, keys and notebooks
+//!
+//! A name is made up of Unicode letters, digits, '-', '_' and '.', and must begin with a letter, digit
+//! or '_'. Section paths are names joined by '/', e.g. `work/client_acme/q3-planning`.
+//! Names are case sensitive
+
+use std::{fmt, error::Error};
+
+use crate::SECTION_SEPARATOR;
+
+// begins the names of files kept next to notebooks that are not notebooks, such as the _kntemp_ temp files
+pub(crate) const RESERVED_FILENAME_PREFIX: char = '_';
+
+/// What a name being checked is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    Section,
+    Key,
+    Notebook
+}
+
+impl fmt::Display for NameKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameKind::Section => write!(f, "section name"),
+            NameKind::Key => write!(f, "key"),
+            NameKind::Notebook => write!(f, "notebook name")
+        }
+    }
+}
+
+/// Why a section name or key breaks the naming rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// the name, or a part of a section path, is empty
+    Empty { kind: NameKind, name: String },
+    /// the name holds a character that is not allowed. position is the 1-based character position
+    InvalidChar { kind: NameKind, name: String, ch: char, position: usize },
+    /// the name begins with a character that is only allowed later in a name
+    InvalidStart { kind: NameKind, name: String, ch: char }
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameError::Empty { kind, name } if name.is_empty() => write!(f, "{} cannot be empty", kind),
+            NameError::Empty { kind, name } => write!(f, "invalid {} '{}': path has an empty part", kind, name),
+            NameError::InvalidChar { kind, name, ch, position } =>
+                write!(f, "invalid {} '{}': {:?} at position {} is not allowed. names may contain letters, digits, '-', '_' and '.'",
+                       kind, name, ch, position),
+            NameError::InvalidStart { kind, name, ch } =>
+                write!(f, "invalid {} '{}': names cannot begin with '{}'", kind, name, ch)
+        }
+    }
+}
+
+impl Error for NameError {}
+
+/// Checks a section name against the naming rules. Nested sections are checked part by part
+///
+/// # Arguments
+///
+/// * `section_name` - name or path of the section
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// assert!(check_section_name("work/client_acme/q3-planning").is_ok());
+/// assert_eq!(check_section_name("q3 planning").unwrap_err().to_string(),
+///            "invalid section name 'q3 planning': ' ' at position 3 is not allowed. names may contain letters, digits, '-', '_' and '.'");
+/// ```
+pub fn check_section_name(section_name: &str) -> Result<(), NameError> {
+    let mut offset = 0;
+    for part in section_name.split(SECTION_SEPARATOR) {
+        if part.is_empty() {
+            return Err(NameError::Empty { kind: NameKind::Section, name: section_name.to_string() });
+        }
+        check_part(NameKind::Section, section_name, part, offset)?;
+        offset += part.chars().count() + 1;
+    }
+    Ok(())
+}
+
+/// Checks an entry key against the naming rules
+///
+/// # Arguments
+///
+/// * `key` - key of the entry
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// assert!(check_key("db.host").is_ok());
+/// assert!(check_key("").is_err());
+/// assert!(check_key("a>b").is_err());
+/// ```
+pub fn check_key(key: &str) -> Result<(), NameError> {
+    if key.is_empty() {
+        return Err(NameError::Empty { kind: NameKind::Key, name: String::new() });
+    }
+    check_part(NameKind::Key, key, key, 0)
+}
+
+/// Checks a notebook name against the naming rules. Notebook names cannot begin with '_', which is
+/// kept for the files written next to notebooks, such as temp files
+///
+/// # Arguments
+///
+/// * `name` - name of the notebook
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+///
+/// assert!(check_notebook_name("work").is_ok());
+/// assert!(check_notebook_name("work/old").is_err());
+/// assert!(check_notebook_name("_kntemp_work").is_err());
+/// ```
+pub fn check_notebook_name(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        return Err(NameError::Empty { kind: NameKind::Notebook, name: String::new() });
+    }
+    check_part(NameKind::Notebook, name, name, 0)?;
+    if name.starts_with(RESERVED_FILENAME_PREFIX) {
+        return Err(NameError::InvalidStart { kind: NameKind::Notebook, name: name.to_string(), ch: RESERVED_FILENAME_PREFIX });
+    }
+    Ok(())
+}
+
+// checks a single name, offset is the character position of the part within the full name
+fn check_part(kind: NameKind, name: &str, part: &str, offset: usize) -> Result<(), NameError> {
+    for (i, ch) in part.chars().enumerate() {
+        if !(ch.is_alphanumeric() || ch == '-' || ch == '_' || ch == '.') {
+            return Err(NameError::InvalidChar { kind, name: name.to_string(), ch, position: offset + i + 1 });
+        }
+        // a leading '-' reads as a command line option, a leading '.' as a hidden name
+        if i == 0 && (ch == '-' || ch == '.') {
+            return Err(NameError::InvalidStart { kind, name: name.to_string(), ch });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_section_name_accepts_policy() {
+        for name in ["project2", "q3-planning", "client_acme", "v1.2", "émigré", "日本", "_drafts", "work/clientA"] {
+            assert!(check_section_name(name).is_ok(), "{} rejected", name);
+        }
+    }
+
+    #[test]
+    fn check_section_name_reports_position_in_path() {
+        let error = check_section_name("work/client acme").unwrap_err();
+        assert_eq!(error, NameError::InvalidChar { kind: NameKind::Section, name: "work/client acme".to_string(), ch: ' ', position: 12 });
+        assert!(matches!(check_section_name("work//a"), Err(NameError::Empty { .. })));
+        assert!(matches!(check_section_name("work/-a"), Err(NameError::InvalidStart { ch: '-', .. })));
+    }
+
+    #[test]
+    fn check_key_rejects_format_characters() {
+        for key in ["a>b", "<a", "\tkey", "a b", "a/b", "-key", "key~"] {
+            assert!(check_key(key).is_err(), "{} accepted", key);
+        }
+    }
+
+    #[test]
+    fn check_notebook_name_rejects_reserved_names() {
+        for name in ["_kntemp", "_kntemp_work", "_drafts"] {
+            assert!(matches!(check_notebook_name(name), Err(NameError::InvalidStart { ch: '_', .. })), "{} accepted", name);
+        }
+        assert!(check_notebook_name("work_kntemp").is_ok());
+    }
+}
@@ -0,0 +1,288 @@
+//! Notebooks are named data files kept together in the keynotes folder. The notebook `work` is stored in
+//! `work.dat`, with its journal, history and backups next to it. The notebook used when none is named
+//! is persisted in the folder, and is `keynotes` until another is chosen
+
+use std::{fs, error::Error, io::Write, path::{Path, PathBuf}};
+
+use crate::{KeynoteFile, check_notebook_name, temp_filepath, naming::RESERVED_FILENAME_PREFIX, error::{already_exists, not_found}, permissions::{create_private_dir, create_private_file}};
+
+/// name of the notebook used when no other has been chosen, kept in keynotes.dat
+pub const DEFAULT_NOTEBOOK: &str = "keynotes";
+
+const NOTEBOOK_EXTENSION: &str = "dat";
+// holds the name of the notebook chosen with set_default
+const DEFAULT_NOTEBOOK_FILENAME: &str = ".default_notebook";
+
+/// The notebooks in a folder
+#[derive(Debug, Clone)]
+pub struct Notebooks {
+    folder: PathBuf
+}
+
+impl Notebooks {
+    /// Returns the notebooks in the keynotes folder in the users home directory
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::*;
+    /// let notebooks = Notebooks::new().unwrap();
+    ///
+    /// assert!(notebooks.folder().ends_with(".keynotes"));
+    /// ```
+    pub fn new() -> Result<Notebooks, Box<dyn Error>> {
+        match home::home_dir() {
+            Some(mut folder) => {
+                folder.push(".keynotes");
+                Ok(Notebooks { folder })
+            },
+            None => Err("error: unable to find home directory".into())
+        }
+    }
+
+    /// Returns the notebooks in any folder
+    ///
+    /// # Arguments
+    ///
+    /// * `folder` - folder the notebooks are kept in
+    pub fn at(folder: &Path) -> Notebooks {
+        Notebooks { folder: folder.to_path_buf() }
+    }
+
+    /// Returns the folder the notebooks are kept in
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Returns the path of the data file of a notebook, whether it exists or not
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    pub fn filepath(&self, name: &str) -> PathBuf {
+        self.folder.join(format!("{}.{}", name, NOTEBOOK_EXTENSION))
+    }
+
+    /// Checks if a notebook exists
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    pub fn exists(&self, name: &str) -> bool {
+        self.filepath(name).is_file()
+    }
+
+    /// Returns the names of the notebooks in the folder, sorted
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::{env, fs};
+    /// use keydata::*;
+    ///
+    /// let folder = env::temp_dir().join("kntest_notebooks_list_doc");
+    /// let notebooks = Notebooks::at(&folder);
+    /// notebooks.create("work").unwrap();
+    /// notebooks.create("home").unwrap();
+    ///
+    /// assert_eq!(notebooks.list().unwrap(), vec!["home", "work"]);
+    ///
+    /// fs::remove_dir_all(folder);  // remove the test folder
+    /// ```
+    pub fn list(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !self.folder.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for dir_entry in fs::read_dir(&self.folder)? {
+            let path = dir_entry?.path();
+            if !path.is_file() || path.extension() != Some(NOTEBOOK_EXTENSION.as_ref()) {
+                continue;
+            }
+            // temp files are not notebooks: _kntemp_<name>.dat, and _kntemp.dat written by older versions
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !name.starts_with(RESERVED_FILENAME_PREFIX) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Creates an empty notebook, and the folder if it does not exist
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook, following the same naming rules as section names, but not beginning with '_'
+    pub fn create(&self, name: &str) -> Result<KeynoteFile, Box<dyn Error>> {
+        check_notebook_name(name)?;
+        if self.exists(name) {
+            return Err(already_exists(format!("notebook '{}' already exists", name)));
+        }
+
+        if !self.folder.exists() {
+            create_private_dir(&self.folder)?;
+        }
+        let filepath = self.filepath(name);
+        create_private_file(&filepath)?;
+
+        Ok(KeynoteFile::with_path(&filepath))
+    }
+
+    /// Returns the KeynoteFile of a notebook, ready to load. Only the default notebook is created
+    /// when it is first written to, others must be created first
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    pub fn open(&self, name: &str) -> Result<KeynoteFile, Box<dyn Error>> {
+        check_notebook_name(name)?;
+        if name != DEFAULT_NOTEBOOK && !self.exists(name) {
+            return Err(not_found(format!("notebook '{}' does not exist", name)));
+        }
+        Ok(KeynoteFile::with_path(&self.filepath(name)))
+    }
+
+    /// Deletes a notebook with its journal, history and backups. If it was the default notebook,
+    /// the default goes back to `keynotes`
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::{env, fs};
+    /// use keydata::*;
+    ///
+    /// let folder = env::temp_dir().join("kntest_notebooks_delete_doc");
+    /// let notebooks = Notebooks::at(&folder);
+    /// notebooks.create("work").unwrap();
+    ///
+    /// notebooks.delete("work").unwrap();
+    /// assert!(!notebooks.exists("work"));
+    ///
+    /// fs::remove_dir_all(folder);  // remove the test folder
+    /// ```
+    pub fn delete(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        check_notebook_name(name)?;
+        if !self.exists(name) {
+            return Err(not_found(format!("notebook '{}' does not exist", name)));
+        }
+
+        let kn_file = KeynoteFile::with_path(&self.filepath(name));
+        for backup in kn_file.list_backups()? {
+            fs::remove_file(backup.path)?;
+        }
+        for path in [kn_file.journal_filepath(), kn_file.history_filepath()] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        fs::remove_file(&kn_file.filepath)?;
+
+        if self.default_notebook()? == name {
+            fs::remove_file(self.folder.join(DEFAULT_NOTEBOOK_FILENAME))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the notebook used when none is named
+    pub fn default_notebook(&self) -> Result<String, Box<dyn Error>> {
+        let path = self.folder.join(DEFAULT_NOTEBOOK_FILENAME);
+        if !path.exists() {
+            return Ok(DEFAULT_NOTEBOOK.to_string());
+        }
+
+        let name = fs::read_to_string(path)?.trim().to_string();
+        if check_notebook_name(&name).is_err() {
+            return Ok(DEFAULT_NOTEBOOK.to_string());
+        }
+        Ok(name)
+    }
+
+    /// Makes a notebook the one used when none is named. The choice is kept in the folder
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of an existing notebook
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::{env, fs};
+    /// use keydata::*;
+    ///
+    /// let folder = env::temp_dir().join("kntest_notebooks_default_doc");
+    /// let notebooks = Notebooks::at(&folder);
+    /// assert_eq!(notebooks.default_notebook().unwrap(), DEFAULT_NOTEBOOK);
+    ///
+    /// notebooks.create("work").unwrap();
+    /// notebooks.set_default("work").unwrap();
+    /// assert_eq!(notebooks.default_notebook().unwrap(), "work");
+    ///
+    /// fs::remove_dir_all(folder);  // remove the test folder
+    /// ```
+    pub fn set_default(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.folder.join(DEFAULT_NOTEBOOK_FILENAME);
+        if name == DEFAULT_NOTEBOOK {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+
+        check_notebook_name(name)?;
+        if !self.exists(name) {
+            return Err(not_found(format!("notebook '{}' does not exist", name)));
+        }
+
+        // written private and through a temp file, like the notebooks, so an interrupted write keeps the old choice
+        let tmp_path = temp_filepath(&path);
+        let mut tmp_file = create_private_file(&tmp_path)?;
+        tmp_file.write_all(format!("{}\n", name).as_bytes())?;
+        tmp_file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn notebooks_are_kept_apart() {
+        let folder = env::temp_dir().join("kntest_notebooks_kept_apart");
+        let notebooks = Notebooks::at(&folder);
+
+        let mut work = notebooks.create("work").unwrap();
+        work.set_journaling(true);
+        work.add_section("leaders").unwrap();
+        work.add_entry("leaders", "atreides", "leto").unwrap();
+        notebooks.create("home").unwrap();
+        assert!(notebooks.create("work").is_err());
+        assert!(notebooks.create("work/old").is_err());
+        assert!(notebooks.create("_kntemp").is_err());
+
+        let mut home = notebooks.open("home").unwrap();
+        home.load_data().unwrap();
+        assert!(home.get_sections().is_empty());
+        assert!(notebooks.open("travel").is_err());
+
+        notebooks.set_default("work").unwrap();
+        assert!(notebooks.set_default("travel").is_err());
+        assert_eq!(notebooks.default_notebook().unwrap(), "work");
+
+        notebooks.delete("work").unwrap();
+        assert!(!work.journal_filepath().exists());
+        // temp files left by interrupted writes are not listed
+        fs::write(folder.join("_kntemp.dat"), "").unwrap();
+        fs::write(folder.join("_kntemp_home.dat"), "").unwrap();
+        assert_eq!(notebooks.list().unwrap(), vec!["home"]);
+        assert_eq!(notebooks.default_notebook().unwrap(), DEFAULT_NOTEBOOK);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
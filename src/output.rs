@@ -0,0 +1,167 @@
+//! How kn writes its results. Text is laid out for people to read, json and tsv are for scripts.
+//! In json a list is an array of objects and a single result is one object. In tsv the first line
+//! names the columns and tabs, newlines and backslashes in values are escaped as \t, \n and \\
+
+use std::error::Error;
+
+use clap::ValueEnum;
+use keydata::ErrorKind;
+use serde_json::{json, Map, Value};
+
+/// format results are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// laid out for people to read
+    Text,
+    /// json, one document per command
+    Json,
+    /// tab separated values, with a header line
+    Tsv
+}
+
+/// Writes results and errors in the chosen format
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: Format,
+    /// when true results are not written, e.g. while a command is run on a copy for a dry run.
+    /// warnings and errors still are
+    pub quiet: bool
+}
+
+impl Output {
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    /// writes the result of a command that changes something
+    pub fn message(&self, message: &str) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            Format::Text | Format::Tsv => println!("{}", message),
+            Format::Json => println!("{}", json!({ "message": message }))
+        }
+    }
+
+    /// writes a list of results, each row holding a value for each column
+    pub fn records(&self, columns: &[&str], rows: Vec<Vec<Value>>) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            Format::Json => {
+                let objects: Vec<Value> = rows.into_iter().map(|row| to_object(columns, row)).collect();
+                println!("{}", Value::Array(objects));
+            },
+            Format::Text | Format::Tsv => {
+                println!("{}", columns.join("\t"));
+                for row in rows {
+                    let cells: Vec<String> = row.iter().map(tsv_cell).collect();
+                    println!("{}", cells.join("\t"));
+                }
+            }
+        }
+    }
+
+    /// writes a single result
+    pub fn record(&self, columns: &[&str], row: Vec<Value>) {
+        if self.quiet {
+            return;
+        }
+        match self.format {
+            Format::Json => println!("{}", to_object(columns, row)),
+            Format::Text | Format::Tsv => self.records(columns, vec![row])
+        }
+    }
+
+    /// writes a warning to stderr
+    pub fn warning(&self, warning: &str) {
+        match self.format {
+            Format::Text => eprintln!("warning: {}", warning),
+            Format::Json => eprintln!("{}", json!({ "warning": warning })),
+            Format::Tsv => eprintln!("warning\t{}", escape(warning))
+        }
+    }
+
+    /// writes an error to stderr, with its kind and the exit code it ends the process with
+    pub fn error(&self, kind: ErrorKind, code: u8, error: &dyn Error) {
+        let kind = kind_name(kind);
+        match self.format {
+            Format::Text => eprintln!("error: {}", error),
+            Format::Json => eprintln!("{}", json!({ "error": { "kind": kind, "code": code, "message": error.to_string() } })),
+            Format::Tsv => eprintln!("error\t{}\t{}\t{}", kind, code, escape(&error.to_string()))
+        }
+    }
+}
+
+/// Finds the format asked for on the command line without parsing it, so errors parsing the
+/// command line can be written in that format too
+pub fn requested_format(args: &[String]) -> Format {
+    let mut format = Format::Text;
+    for (i, arg) in args.iter().enumerate() {
+        let value = match arg.as_str() {
+            "--json" => Some("json"),
+            "--format" => args.get(i + 1).map(|value| value.as_str()),
+            _ => arg.strip_prefix("--format=")
+        };
+        if let Some(requested) = value.and_then(|value| Format::from_str(value, true).ok()) {
+            format = requested;
+        }
+    }
+    format
+}
+
+fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::AlreadyExists => "already_exists",
+        ErrorKind::InvalidInput => "invalid_input",
+        ErrorKind::Corrupt => "corrupt",
+        ErrorKind::Io => "io",
+        ErrorKind::Other => "other"
+    }
+}
+
+fn to_object(columns: &[&str], row: Vec<Value>) -> Value {
+    let fields: Map<String, Value> = columns.iter().map(|column| column.to_string()).zip(row).collect();
+    Value::Object(fields)
+}
+
+fn tsv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => escape(text),
+        other => other.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn format_is_found_before_parsing() {
+        assert_eq!(requested_format(&args("kn section list --json")), Format::Json);
+        assert_eq!(requested_format(&args("kn --format tsv entry list")), Format::Tsv);
+        assert_eq!(requested_format(&args("kn --format=json entry get")), Format::Json);
+        assert_eq!(requested_format(&args("kn entry get key")), Format::Text);
+    }
+
+    #[test]
+    fn tsv_cells_are_escaped() {
+        assert_eq!(tsv_cell(&json!("a\tb\nc\\d")), "a\\tb\\nc\\\\d");
+        assert_eq!(tsv_cell(&Value::Null), "");
+        assert_eq!(tsv_cell(&json!(3)), "3");
+        assert_eq!(to_object(&["section", "key"], vec![json!("leaders"), json!("atreides")]),
+                   json!({ "section": "leaders", "key": "atreides" }));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{fmt, error::Error};
+
+use crate::{Metadata, Recovery, PermissionWarning};
+
+/// Options controlling how a keynotes data file is parsed. The default is lenient, as `load_data` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// when true, any malformed line fails the load. when false, malformed lines are skipped and reported as warnings
+    pub strict: bool
+}
+
+impl ParseOptions {
+    /// Returns options that fail the load if any line is malformed
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::ParseOptions;
+    /// assert!(ParseOptions::strict().strict);
+    /// ```
+    pub fn strict() -> ParseOptions {
+        ParseOptions { strict: true }
+    }
+
+    /// Returns options that skip malformed lines and collect them as warnings
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::ParseOptions;
+    /// assert!(!ParseOptions::lenient().strict);
+    /// ```
+    pub fn lenient() -> ParseOptions {
+        ParseOptions { strict: false }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions::lenient()
+    }
+}
+
+/// A problem found on a single line of a data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    /// line number, starting at 1
+    pub line: usize,
+    /// column the problem was found at, starting at 1
+    pub column: usize,
+    /// description of the problem
+    pub reason: String
+}
+
+impl ParseDiagnostic {
+    /// Returns a new ParseDiagnostic
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - line number, starting at 1
+    /// * `column` - column number, starting at 1
+    /// * `reason` - description of the problem
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::ParseDiagnostic;
+    /// let d = ParseDiagnostic::new(3, 1, "entry appears before any section header");
+    /// assert_eq!(d.to_string(), "line 3, column 1: entry appears before any section header");
+    /// ```
+    pub fn new(line: usize, column: usize, reason: &str) -> ParseDiagnostic {
+        ParseDiagnostic { line, column, reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.reason)
+    }
+}
+
+/// Error returned by a strict load when one or more lines are malformed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// every problem found in the file, in line order
+    pub diagnostics: Vec<ParseDiagnostic>
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: file format corrupted ({} problem{})", self.diagnostics.len(),
+                                                                 if self.diagnostics.len() == 1 { "" } else { "s" })?;
+        for d in &self.diagnostics {
+            write!(f, "\n\t{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+/// Summary of a successful load
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// malformed lines that were skipped during a lenient load
+    pub warnings: Vec<ParseDiagnostic>,
+    /// clean up done after an interrupted rewrite of the file
+    pub recoveries: Vec<Recovery>,
+    /// the data file, or the folder holding it, can be accessed by other users
+    pub permissions: Vec<PermissionWarning>
+}
+
+/// A single classified line of a data file
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Line<'a> {
+    Section(&'a str),
+    Entry(&'a str, &'a str),
+    Meta(Metadata),
+    Blank
+}
+
+/// Classifies a line of a data file, or returns the column and reason it is malformed
+pub(crate) fn parse_line(line: &str) -> Result<Line<'_>, (usize, String)> {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+
+    if line.trim().is_empty() {
+        return Ok(Line::Blank);
+    }
+
+    if let Some(rest) = line.strip_prefix("\t@") {                         // metadata for the line above
+        return match Metadata::from_fields(rest) {
+            Ok(metadata) => Ok(Line::Meta(metadata)),
+            Err((offset, reason)) => Err((column_of(line, offset + 2), reason))
+        };
+    }
+
+    if let Some(rest) = line.strip_prefix('\t') {                          // entry
+        if !rest.starts_with('<') {
+            return Err((2, "expected '<' to open the entry key".to_string()));
+        }
+        let close = match rest.find('>') {
+            Some(i) => i,
+            None => return Err((column_of(line, line.len()), "missing '>' after entry key".to_string()))
+        };
+        let key = &rest[1..close];
+        let value = &rest[close + 1..];
+        return match value.strip_suffix("<~>") {
+            Some(value) => Ok(Line::Entry(key, value)),
+            None => Err((column_of(line, line.len()), "entry value is not terminated by '<~>'".to_string()))
+        };
+    }
+
+    if line.starts_with('<') {                                             // section header
+        if !line.ends_with('>') || line.len() < 2 {
+            return Err((column_of(line, line.len()), "section header is missing closing '>'".to_string()));
+        }
+        let name = &line[1..line.len() - 1];
+        if name.is_empty() {
+            return Err((2, "section name is empty".to_string()));
+        }
+        if let Some(i) = name.find(['<', '>']) {
+            return Err((column_of(line, i + 1), "section name contains '<' or '>'".to_string()));
+        }
+        return Ok(Line::Section(name));
+    }
+
+    Err((1, "line is neither a section header nor a tab-prefixed entry".to_string()))
+}
+
+// 1-based character column of a byte offset
+fn column_of(line: &str, byte_offset: usize) -> usize {
+    line[..byte_offset].chars().count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_section_success() {
+        assert_eq!(parse_line("<leaders>\n"), Ok(Line::Section("leaders")));
+    }
+
+    #[test]
+    fn parse_line_entry_success() {
+        assert_eq!(parse_line("\t<atreides>leto<~>\n"), Ok(Line::Entry("atreides", "leto")));
+    }
+
+    #[test]
+    fn parse_line_meta_success() {
+        let metadata = Metadata { created: Some(10), modified: Some(20), ..Default::default() };
+        assert_eq!(parse_line("\t@created=10;modified=20\n"), Ok(Line::Meta(metadata)));
+    }
+
+    #[test]
+    fn parse_line_meta_invalid_reports_column() {
+        let result = parse_line("\t@created=ten");
+        assert_eq!(result.unwrap_err().0, 11);
+    }
+
+    #[test]
+    fn parse_line_blank_success() {
+        assert_eq!(parse_line("   \n"), Ok(Line::Blank));
+    }
+
+    #[test]
+    fn parse_line_missing_terminator_reports_end_of_line() {
+        let result = parse_line("\t<atreides>leto\n");
+        assert_eq!(result, Err((16, "entry value is not terminated by '<~>'".to_string())));
+    }
+
+    #[test]
+    fn parse_line_entry_missing_open_bracket() {
+        let result = parse_line("\tatreides>leto<~>");
+        assert_eq!(result.unwrap_err().0, 2);
+    }
+
+    #[test]
+    fn parse_line_unterminated_header() {
+        let result = parse_line("<leaders");
+        assert_eq!(result.unwrap_err().0, 9);
+    }
+
+    #[test]
+    fn parse_line_stray_text() {
+        let result = parse_line("just some text");
+        assert_eq!(result.unwrap_err().0, 1);
+    }
+}
@@ -0,0 +1,116 @@
+//! Keeps notes private to the user that owns them. On Unix the keynotes folder is created with mode 0700 and
+//! every file in it with mode 0600. Other platforms keep their default permissions
+
+use std::{fmt, fs, fs::{File, OpenOptions}, io, path::{Path, PathBuf}};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+/// A file or folder that users other than its owner can access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionWarning {
+    /// path to the file or folder
+    pub path: PathBuf,
+    /// its permission bits, e.g. 0o644
+    pub mode: u32
+}
+
+impl fmt::Display for PermissionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let private_mode = if self.path.is_dir() { "700" } else { "600" };
+        write!(f, "{} can be accessed by other users (mode {:03o}). run chmod {} {} to make it private",
+               self.path.display(), self.mode, private_mode, self.path.display())
+    }
+}
+
+/// Creates a folder that only the user can access. Its parent must exist
+pub(crate) fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(path)
+}
+
+/// Returns OpenOptions that create files only the user can read and write
+pub(crate) fn private_open_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+}
+
+/// Creates, or truncates, a file that only the user can read and write
+pub(crate) fn create_private_file(path: &Path) -> io::Result<File> {
+    private_open_options().write(true).create(true).truncate(true).open(path)
+}
+
+/// Gives a file that is about to replace another the permissions of the file it replaces, so a rewrite
+/// keeps any permissions the user has chosen. Does nothing if there is no file to replace
+pub(crate) fn copy_permissions(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::metadata(from) {
+        Ok(metadata) => fs::set_permissions(to, metadata.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+    }
+}
+
+/// Checks if a file or folder can be accessed by the group or other users
+pub(crate) fn check_permissions(path: &Path) -> io::Result<Option<PermissionWarning>> {
+    #[cfg(unix)]
+    {
+        let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Ok(Some(PermissionWarning { path: path.to_path_buf(), mode }));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(None)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{KeynoteFile, Notebooks};
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn files_are_created_private_and_rewrites_keep_permissions() {
+        let mut kn_file = KeynoteFile::new("kntest_permissions.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("leaders").unwrap();
+        assert_eq!(mode_of(&kn_file.filepath), 0o600);
+        assert_eq!(mode_of(&kn_file.journal_filepath()), 0o600);
+        assert!(check_permissions(&kn_file.filepath).unwrap().is_none());
+
+        // a mode the user chose survives the temp file rewrite
+        fs::set_permissions(&kn_file.filepath, fs::Permissions::from_mode(0o640)).unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        assert_eq!(mode_of(&kn_file.filepath), 0o640);
+
+        let report = kn_file.load_data_with_options(&crate::ParseOptions::lenient()).unwrap();
+        assert!(report.permissions.contains(&PermissionWarning { path: kn_file.filepath.clone(), mode: 0o640 }));
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+
+    #[test]
+    fn default_notebook_choice_is_private() {
+        let folder = std::env::temp_dir().join("kntest_permissions_default_notebook");
+        let notebooks = Notebooks::at(&folder);
+        notebooks.create("work").unwrap();
+        notebooks.set_default("work").unwrap();
+
+        let path = folder.join(".default_notebook");
+        assert_eq!(mode_of(&path), 0o600);
+        assert!(check_permissions(&path).unwrap().is_none());
+        assert_eq!(fs::read_dir(&folder).unwrap().count(), 2);     // no temp file is left behind
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
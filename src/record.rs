@@ -0,0 +1,70 @@
+//! Helpers for the line based record files kept next to a data file. A record is one line of tab separated
+//! fields, with tabs, newlines and backslashes in a field escaped
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Joins fields into a single record line, ending in a newline
+pub(crate) fn join_fields(fields: &[&str]) -> String {
+    let mut line = fields.iter().map(|field| escape_field(field)).collect::<Vec<String>>().join("\t");
+    line.push('\n');
+    line
+}
+
+/// Splits a record line back into its unescaped fields
+pub(crate) fn split_fields(line: &str) -> Vec<String> {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.split('\t').map(unescape_field).collect()
+}
+
+/// Seconds since the unix epoch
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn unescape_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\')
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        let fields = ["add_entry", "leaders", "key\twith tab", "back\\slash\nnewline", ""];
+        let line = join_fields(&fields);
+
+        assert_eq!(line.matches('\t').count(), 4);
+        assert!(line.ends_with('\n') && !line[..line.len() - 1].contains('\n'));
+        assert_eq!(split_fields(&line), fields);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{fmt, fs::{self, File}, error::Error, path::{Path, PathBuf}};
+
+use crate::{parse::parse_line, temp_filepath, legacy_temp_filepath, encryption::Cipher, is_encrypted, error::invalid_input,
+            permissions::private_open_options};
+
+// kept in the folder of the data files, and never removed, so every process locks the same file
+const WRITE_LOCK_FILENAME: &str = ".write_lock";
+
+/// Held while a data file is rewritten through its temp file, and while temp files are recovered.
+/// A temp file found while holding the lock cannot belong to a write still in progress. Released when dropped
+pub(crate) struct WriteLock {
+    _file: File
+}
+
+/// Waits for, then takes, the lock on writes to the data files in the folder of `filepath`
+pub(crate) fn lock_writes(filepath: &Path) -> Result<WriteLock, Box<dyn Error>> {
+    let file = private_open_options().write(true).create(true).truncate(false).open(filepath.with_file_name(WRITE_LOCK_FILENAME))?;
+    file.lock()?;
+    Ok(WriteLock { _file: file })
+}
+
+/// An action taken on load to clean up after a rewrite that was interrupted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// the data file was intact, the stale temp file at this path was deleted
+    DiscardedTemp(PathBuf),
+    /// the data file was missing, the complete temp file at this path was renamed to replace it
+    RolledForward(PathBuf),
+    /// the data file was missing and the temp file could not be verified, it was moved aside for inspection
+    Quarantined { temp: PathBuf, moved_to: PathBuf }
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recovery::DiscardedTemp(temp) =>
+                write!(f, "discarded stale temp file {} left by an interrupted write", temp.display()),
+            Recovery::RolledForward(temp) =>
+                write!(f, "restored data file from {} left by an interrupted write", temp.display()),
+            Recovery::Quarantined { temp, moved_to } =>
+                write!(f, "temp file {} left by an interrupted write is damaged, moved to {}", temp.display(), moved_to.display())
+        }
+    }
+}
+
+/// Detects temp files left by an interrupted rewrite of the data file at `filepath` and cleans them up.
+///
+/// The write lock is held while looking, so a write in progress in another process is waited for and
+/// any temp file found was left by a write that died. Writers finish the temp file before replacing
+/// the data file with it, so while the data file exists such a temp file is stale and is discarded.
+/// If the data file is missing the write died after removing it, so the temp file is rolled forward,
+/// as long as every line in it parses
+pub(crate) fn recover_interrupted_write(filepath: &Path, cipher: Option<&Cipher>) -> Result<Vec<Recovery>, Box<dyn Error>> {
+    let mut recoveries = Vec::new();
+    let temps: Vec<PathBuf> = vec![Some(temp_filepath(filepath)), legacy_temp_filepath(filepath)].into_iter().flatten()
+        .filter(|temp| temp != filepath && temp.exists())
+        .collect();
+    if temps.is_empty() {
+        return Ok(recoveries);
+    }
+
+    let _lock = lock_writes(filepath)?;
+    for temp in temps {
+        // the write that was waited for has renamed its temp file into place
+        if !temp.exists() {
+            continue;
+        }
+
+        if filepath.exists() {
+            fs::remove_file(&temp)?;
+            recoveries.push(Recovery::DiscardedTemp(temp));
+        }
+        else if is_complete_data_file(&temp, cipher)? {
+            fs::rename(&temp, filepath)?;
+            recoveries.push(Recovery::RolledForward(temp));
+        }
+        else {
+            let moved_to = quarantine_filepath(filepath);
+            fs::rename(&temp, &moved_to)?;
+            recoveries.push(Recovery::Quarantined { temp, moved_to });
+        }
+    }
+
+    Ok(recoveries)
+}
+
+// a path next to the data file that no earlier quarantined temp file holds, e.g. keynotes.recovered-2
+fn quarantine_filepath(filepath: &Path) -> PathBuf {
+    let moved_to = filepath.with_extension("recovered");
+    let mut n = 2;
+    let mut path = moved_to.clone();
+    while path.exists() {
+        path = PathBuf::from(format!("{}-{}", moved_to.display(), n));
+        n += 1;
+    }
+    path
+}
+
+// true when every line of the file parses and the last line was written out in full.
+// an encrypted file is complete when it decrypts, the authentication tag covers every byte
+fn is_complete_data_file(path: &Path, cipher: Option<&Cipher>) -> Result<bool, Box<dyn Error>> {
+    let mut bytes = fs::read(path)?;
+    if is_encrypted(path)? {
+        bytes = match cipher {
+            Some(cipher) => match cipher.open(&bytes) {
+                Ok(bytes) => bytes,
+                Err(_) => return Ok(false)
+            },
+            None => return Err(invalid_input("data file is encrypted. a passphrase is needed to recover it"))
+        };
+    }
+
+    let contents = match String::from_utf8(bytes) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(false)
+    };
+
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        return Ok(false);
+    }
+
+    Ok(contents.lines().all(|line| parse_line(line).is_ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeynoteFile;
+
+    fn get_test_paths(filename: &str) -> (PathBuf, PathBuf) {
+        let kn_file = KeynoteFile::new(filename).unwrap();
+        fs::create_dir_all(kn_file.filepath.parent().unwrap()).unwrap();
+        let temp = temp_filepath(&kn_file.filepath);
+        (kn_file.filepath, temp)
+    }
+
+    #[test]
+    fn recover_discards_temp_when_data_file_exists() {
+        let (path, temp) = get_test_paths("kntest_recover_discard.dat");
+        fs::write(&path, "<leaders>\n").unwrap();
+        fs::write(&temp, "<leaders>\n\t<atrei").unwrap();
+
+        let recoveries = recover_interrupted_write(&path, None).unwrap();
+
+        assert_eq!(recoveries, vec![Recovery::DiscardedTemp(temp.clone())]);
+        assert!(!temp.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<leaders>\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn recover_rolls_forward_when_data_file_missing() {
+        let (path, temp) = get_test_paths("kntest_recover_forward.dat");
+        fs::write(&temp, "<leaders>\n\t<atreides>leto<~>\n").unwrap();
+
+        let recoveries = recover_interrupted_write(&path, None).unwrap();
+
+        assert_eq!(recoveries, vec![Recovery::RolledForward(temp.clone())]);
+        assert!(!temp.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<leaders>\n\t<atreides>leto<~>\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn recover_quarantines_damaged_temp() {
+        let (path, temp) = get_test_paths("kntest_recover_damaged.dat");
+        fs::write(&temp, "<leaders>\n\t<atrei").unwrap();
+
+        let recoveries = recover_interrupted_write(&path, None).unwrap();
+
+        let moved_to = path.with_extension("recovered");
+        assert_eq!(recoveries, vec![Recovery::Quarantined { temp: temp.clone(), moved_to: moved_to.clone() }]);
+        assert!(!path.exists());
+        assert!(moved_to.exists());
+
+        // a later damaged temp file does not replace the one kept before
+        fs::write(&temp, "<fremen>\n\t<stil").unwrap();
+        let recoveries = recover_interrupted_write(&path, None).unwrap();
+        let moved_again_to = PathBuf::from(format!("{}-2", moved_to.display()));
+        assert_eq!(recoveries, vec![Recovery::Quarantined { temp, moved_to: moved_again_to.clone() }]);
+        assert_eq!(fs::read_to_string(&moved_to).unwrap(), "<leaders>\n\t<atrei");
+        fs::remove_file(moved_to).unwrap();
+        fs::remove_file(moved_again_to).unwrap();
+    }
+
+    #[test]
+    fn recover_leaves_the_legacy_temp_to_the_default_data_file() {
+        let (path, _) = get_test_paths("kntest_recover_legacy.dat");
+        assert_eq!(legacy_temp_filepath(&path), None);
+        assert!(recover_interrupted_write(&path, None).unwrap().is_empty());
+
+        let default_path = path.with_file_name("keynotes.dat");
+        assert_eq!(legacy_temp_filepath(&default_path), Some(path.with_file_name("_kntemp.dat")));
+    }
+
+    #[test]
+    fn recover_waits_for_a_write_in_progress() {
+        let (path, temp) = get_test_paths("kntest_recover_in_progress.dat");
+        fs::write(&path, "<leaders>\n").unwrap();
+
+        // another writer holds the lock while its temp file is half written, then replaces the data file
+        let lock = lock_writes(&path).unwrap();
+        fs::write(&temp, "<leaders>\n\t<atreides>leto<~>\n").unwrap();
+        let writer = {
+            let (path, temp) = (path.clone(), temp.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                fs::rename(temp, path).unwrap();
+                drop(lock);
+            })
+        };
+
+        assert!(recover_interrupted_write(&path, None).unwrap().is_empty());
+        writer.join().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<leaders>\n\t<atreides>leto<~>\n");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::error::Error;
+
+use crate::{FileItem, KeynoteFile, Metadata, Operation, Section, check_key, check_section_name, error::{already_exists, corrupt, invalid_input, not_found}};
+
+impl KeynoteFile {
+    /// Gives an entry a new key, keeping its value, tags and section. New keys are checked with `check_key`
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry to rename
+    /// * `new_key` - key to give it
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_rename_entry_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    ///
+    /// kn_file.rename_entry("atreides", "duke").unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("duke"), Some("leto"));
+    /// assert!(!kn_file.contains_key("atreides"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn rename_entry(&mut self, key: &str, new_key: &str) -> Result<(), Box<dyn Error>> {
+        check_key(new_key)?;
+
+        let (section_name, value) = match self.find_entry(key) {
+            Some(entry) => entry,
+            None => return Err(not_found(format!("key: '{}' does not exist. nothing renamed.", key)))
+        };
+        if new_key == key {
+            return Ok(());
+        }
+        if self.contains_key(new_key) {
+            return Err(already_exists(format!("key: {} already exists. nothing renamed.", new_key)));
+        }
+
+        // rewrite the entry line in place, so its metadata line stays with it
+        let mut items = self.read_items()?;
+        let index = match KeynoteFile::find_entry_item(&items, key) {
+            Some(index) => index,
+            None => return Err(corrupt("error: file corrupted"))
+        };
+        items[index].line = KeynoteFile::build_entry_string(new_key, &value);
+        let metadata = KeynoteFile::touch_item(&mut items[index]);
+        let section_metadata = match KeynoteFile::find_section_item(&items, &section_name) {
+            Some(header) => KeynoteFile::touch_item(&mut items[header]),
+            None => return Err(corrupt("error: file corrupted"))
+        };
+
+        self.write_items(&items)?;
+
+        if let Some(section) = self.get_section(&section_name) {
+            section.data.remove(key);
+            section.entry_metadata.remove(key);
+            section.add_entry(new_key, &value);
+            section.entry_metadata.insert(new_key.to_string(), metadata);
+            section.metadata = section_metadata;
+        }
+
+        self.record_operation(Operation::RenameEntry { section: section_name, key: key.to_string(), new_key: new_key.to_string() })?;
+
+        Ok(())
+    }
+
+    /// Gives a section a new name, moving the sections nested in it along with it. Missing parent
+    /// sections of the new name are added. Names are checked with `check_section_name`
+    ///
+    /// # Arguments
+    ///
+    /// * `section_name` - name or path of the section to rename
+    /// * `new_name` - name or path to give it
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_rename_section_doc.dat").unwrap();
+    /// kn_file.add_section("work/clientA").unwrap();
+    /// kn_file.add_entry("work/clientA", "host", "db.example.com").unwrap();
+    ///
+    /// kn_file.rename_section("work", "jobs").unwrap();
+    /// assert!(kn_file.get_section("jobs/clientA").is_some());
+    /// assert!(kn_file.get_section("work").is_none());
+    /// assert_eq!(kn_file.get_value_from_key("host"), Some("db.example.com"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn rename_section(&mut self, section_name: &str, new_name: &str) -> Result<(), Box<dyn Error>> {
+        check_section_name(new_name)?;
+
+        if self.get_section(section_name).is_none() {
+            return Err(not_found(format!("section: '{}' does not exist. nothing renamed.", section_name)));
+        }
+        if new_name == section_name {
+            return Ok(());
+        }
+
+        // missing parents of the new name are added in the same write, outermost first
+        let mut parents: Vec<String> = Vec::new();
+        let mut parent = Section::parent_path(new_name);
+        while let Some(name) = parent {
+            if self.get_section(name).is_none() {
+                parents.insert(0, name.to_string());
+            }
+            parent = Section::parent_path(name);
+        }
+
+        self.move_section(section_name, new_name, &parents, &[])?;
+        self.record_operation(Operation::RenameSection { section: section_name.to_string(), new_name: new_name.to_string(), parents })
+    }
+
+    // renames a section, and those nested in it, in a single write. the parents in add_parents are added,
+    // and those in remove_parents removed if nothing is left in them
+    pub(crate) fn move_section(&mut self, section_name: &str, new_name: &str, add_parents: &[String], remove_parents: &[String])
+        -> Result<(), Box<dyn Error>> {
+        if self.get_section(section_name).is_none() {
+            return Err(not_found(format!("section: '{}' does not exist. nothing renamed.", section_name)));
+        }
+        if Section::is_within(new_name, section_name) {
+            return Err(invalid_input(format!("cannot move '{}' into itself", section_name)));
+        }
+        if self.get_section(new_name).is_some() {
+            return Err(already_exists(format!("section: '{}' already exists. nothing renamed.", new_name)));
+        }
+
+        // the section and those nested in it, with the names they are given
+        let renames: Vec<(String, String)> = self.sections.keys()
+            .filter(|name| Section::is_within(name, section_name))
+            .map(|name| (name.clone(), format!("{}{}", new_name, &name[section_name.len()..])))
+            .collect();
+
+        let mut items = self.read_items()?;
+        for (old, new) in &renames {
+            let header = match KeynoteFile::find_section_item(&items, old) {
+                Some(header) => header,
+                None => return Err(corrupt("error: file corrupted"))
+            };
+            items[header].line = Section::build_section_string(new);
+        }
+        let section_metadata = match KeynoteFile::find_section_item(&items, new_name) {
+            Some(header) => KeynoteFile::touch_item(&mut items[header]),
+            None => return Err(corrupt("error: file corrupted"))
+        };
+
+        let parent_metadata = Metadata::created_now();
+        for name in add_parents {
+            items.push(FileItem { line: Section::build_section_string(name), meta: Some(parent_metadata.clone()) });
+        }
+
+        // parents to remove go innermost first, once no entry or other section is left in them
+        let mut names: Vec<String> = self.sections.keys().filter(|name| !Section::is_within(name, section_name)).cloned().collect();
+        names.extend(renames.iter().map(|(_, new)| new.clone()));
+        let mut removed = Vec::new();
+        for name in remove_parents.iter().rev() {
+            let is_empty = self.sections.get(name).is_some_and(|section| section.data.is_empty());
+            if !is_empty || names.iter().any(|other| other != name && Section::is_within(other, name)) {
+                continue;
+            }
+            if let Some(header) = KeynoteFile::find_section_item(&items, name) {
+                items.remove(header);
+            }
+            names.retain(|other| other != name);
+            removed.push(name);
+        }
+
+        self.write_items(&items)?;
+
+        for (old, new) in renames {
+            if let Some(mut section) = self.sections.remove(&old) {
+                section.name = new.clone();
+                if new == new_name {
+                    section.metadata = section_metadata.clone();
+                }
+                self.sections.insert(new, section);
+            }
+        }
+        for name in add_parents {
+            let mut section = Section::new(name);
+            section.metadata = parent_metadata.clone();
+            self.sections.insert(name.clone(), section);
+        }
+        for name in removed {
+            self.sections.remove(name);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::{KeynoteFile, ErrorKind, TagMatch};
+
+    #[test]
+    fn renames_keep_entries_and_can_be_undone() {
+        let mut kn_file = KeynoteFile::new("kntest_renames.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("work/clientA").unwrap();
+        kn_file.add_section("workshop").unwrap();
+        kn_file.add_entry("work/clientA", "host", "db.example.com").unwrap();
+        kn_file.tag("host", "servers").unwrap();
+
+        kn_file.rename_entry("host", "db-host").unwrap();
+        kn_file.rename_section("work", "archive/jobs").unwrap();
+
+        let mut reloaded = KeynoteFile::new("kntest_renames.dat").unwrap();
+        reloaded.load_data().unwrap();
+        let mut names: Vec<&String> = reloaded.get_sections().keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["archive", "archive/jobs", "archive/jobs/clientA", "workshop"]);
+        assert_eq!(reloaded.get_value_from_key("db-host"), Some("db.example.com"));
+        assert_eq!(reloaded.entries_with_tags(&["servers"], TagMatch::All), vec![("archive/jobs/clientA", "db-host")]);
+
+        let kind = |result: Result<(), Box<dyn std::error::Error>>| ErrorKind::of(result.unwrap_err().as_ref());
+        assert_eq!(kind(kn_file.rename_section("archive", "archive/old")), ErrorKind::InvalidInput);
+        assert_eq!(kind(kn_file.rename_section("archive", "workshop")), ErrorKind::AlreadyExists);
+        assert_eq!(kind(kn_file.rename_entry("host", "db")), ErrorKind::NotFound);
+
+        // the missing parent added by the section rename goes with the same undo
+        kn_file.undo(1).unwrap();
+        assert!(kn_file.get_section("work/clientA").is_some());
+        assert!(kn_file.get_section("archive").is_none());
+        kn_file.load_data().unwrap();
+        assert!(kn_file.get_section("archive").is_none());
+
+        kn_file.undo(1).unwrap();
+        assert_eq!(kn_file.get_value_from_key("host"), Some("db.example.com"));
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
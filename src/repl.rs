@@ -0,0 +1,302 @@
+//! Interactive mode of kn. The notebook is loaded once and commands are read in a loop, with
+//! line editing, history and tab completion of keys and sections. A current section, picked with
+//! `use`, lets entries be added without naming their section
+
+use std::{error::Error, borrow::Cow};
+
+use keydata::{ErrorKind, KeynoteError, KeynoteFile, masked_value};
+use rustyline::{
+    Context, Editor, Helper,
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator
+};
+
+const HELP: &str = "commands:
+  add [section] <key> <value>   add an entry, to the current section if none is given
+  get <key>                     show the value of an entry
+  rm <key>                      remove an entry
+  ls [section]                  list the entries of a section, or the sections
+  find <text>                   list entries whose key or value holds text
+  use [section]                 make section the current one, or clear it
+  help                          show this list
+  quit                          leave, as does ctrl-d";
+
+const COMMANDS: &[&str] = &["add", "get", "rm", "ls", "find", "use", "help", "quit", "exit"];
+
+/// what a command line asks the session to do next
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// print these lines and read the next command
+    Lines(Vec<String>),
+    /// leave interactive mode
+    Quit
+}
+
+/// A loaded notebook and the section commands act on
+pub struct Session {
+    file: KeynoteFile,
+    section: Option<String>
+}
+
+impl Session {
+    pub fn new(file: KeynoteFile) -> Session {
+        Session { file, section: None }
+    }
+
+    /// text shown before each command, naming the current section
+    pub fn prompt(&self) -> String {
+        match &self.section {
+            Some(section) => format!("kn:{}> ", section),
+            None => String::from("kn> ")
+        }
+    }
+
+    /// Runs one command line
+    pub fn execute(&mut self, line: &str) -> Result<Reply, Box<dyn Error>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Reply::Lines(Vec::new()))
+        };
+
+        let lines = match (command, args) {
+            ("quit" | "exit", _) => return Ok(Reply::Quit),
+            ("help", _) => HELP.lines().map(|line| line.to_string()).collect(),
+            ("add", args) => self.add(args)?,
+            ("get", [key]) => {
+                let value = match self.file.get_value_from_key(key) {
+                    Some(value) => masked_value(value).to_string(),
+                    None => return Err(invalid(ErrorKind::NotFound, format!("key {} does not exist", key)))
+                };
+                vec![format!("{}:   {}", key, value)]
+            },
+            ("rm", [key]) => {
+                self.file.remove_entry(key)?;
+                vec![format!("removed {}", key)]
+            },
+            ("ls", []) => match self.section.clone() {
+                Some(section) => self.entries_of(&section)?,
+                None => self.section_names()
+            },
+            ("ls", [section]) => self.entries_of(section)?,
+            ("find", words) if !words.is_empty() => {
+                let text = words.join(" ");
+                let matches = self.file.search(&text);
+                if matches.is_empty() {
+                    vec![format!("nothing holds '{}'", text)]
+                }
+                else {
+                    matches.iter().map(|(section, key)| format!("{}  {}", section, key)).collect()
+                }
+            },
+            ("use", []) => {
+                self.section = None;
+                Vec::new()
+            },
+            ("use", [section]) => {
+                if self.file.get_section(section).is_none() {
+                    return Err(invalid(ErrorKind::NotFound, format!("section {} does not exist", section)));
+                }
+                self.section = Some(section.to_string());
+                Vec::new()
+            },
+            (command, _) if COMMANDS.contains(&command) => return Err(invalid(ErrorKind::InvalidInput, usage(command))),
+            (command, _) => return Err(invalid(ErrorKind::InvalidInput, format!("unknown command '{}'. type help for the list", command)))
+        };
+
+        Ok(Reply::Lines(lines))
+    }
+
+    // the first word names the section when it is one and a key and value follow it
+    fn add(&mut self, args: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+        let (section, key, value) = match args {
+            [first, key, value @ ..] if !value.is_empty() && self.file.get_section(first).is_some() =>
+                (first.to_string(), *key, value.join(" ")),
+            [key, value @ ..] if !value.is_empty() => match &self.section {
+                Some(section) => (section.clone(), *key, value.join(" ")),
+                None => return Err(invalid(ErrorKind::InvalidInput, "no current section. name one, or pick one with use"))
+            },
+            _ => return Err(invalid(ErrorKind::InvalidInput, usage("add")))
+        };
+
+        self.file.add_entry(&section, key, &value)?;
+        Ok(vec![format!("added <{}>  {}  to  {}", key, value, section)])
+    }
+
+    fn entries_of(&mut self, section: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let section = match self.file.get_section(section) {
+            Some(section) => section,
+            None => return Err(invalid(ErrorKind::NotFound, format!("section {} does not exist", section)))
+        };
+
+        let mut entries: Vec<(&String, &String)> = section.data.iter().collect();
+        entries.sort();
+        let width = entries.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+        Ok(entries.iter().map(|(key, value)| format!("{:<width$}  {}", key, masked_value(value), width = width)).collect())
+    }
+
+    fn section_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.file.get_sections().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.file.get_sections().values().flat_map(|section| section.data.keys().cloned()).collect();
+        keys.sort();
+        keys
+    }
+}
+
+/// Completes command names, keys and section names, from the notebook as it was after the last command
+#[derive(Default)]
+pub struct NameCompleter {
+    keys: Vec<String>,
+    sections: Vec<String>
+}
+
+impl Completer for NameCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..pos];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let names: &[String] = match previous.as_slice() {
+            [] => return Ok((start, candidates(COMMANDS.iter().copied(), word))),
+            ["get" | "rm"] => &self.keys,
+            ["ls" | "use" | "add"] => &self.sections,
+            _ => &[]
+        };
+        Ok((start, candidates(names.iter().map(|name| name.as_str()), word)))
+    }
+}
+
+impl Hinter for NameCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for NameCompleter {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        Cow::Borrowed(prompt)
+    }
+}
+
+impl Validator for NameCompleter {}
+
+impl Helper for NameCompleter {}
+
+/// Reads and runs commands on a loaded notebook until the user quits
+pub fn run(file: KeynoteFile) -> Result<(), Box<dyn Error>> {
+    let mut session = Session::new(file);
+    // history is kept for the session only, so values typed never reach the disk unencrypted
+    let mut editor: Editor<NameCompleter, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(NameCompleter::default()));
+    println!("type help for the list of commands");
+
+    loop {
+        if let Some(completer) = editor.helper_mut() {
+            completer.keys = session.keys();
+            completer.sections = session.section_names();
+        }
+
+        let line = match editor.readline(&session.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(Box::new(e))
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        match session.execute(&line) {
+            Ok(Reply::Lines(lines)) => lines.iter().for_each(|line| println!("{}", line)),
+            Ok(Reply::Quit) => break,
+            Err(e) => eprintln!("error: {}", e)
+        }
+    }
+
+    Ok(())
+}
+
+fn candidates<'a>(names: impl Iterator<Item = &'a str>, word: &str) -> Vec<Pair> {
+    names.filter(|name| name.starts_with(word))
+         .map(|name| Pair { display: name.to_string(), replacement: format!("{} ", name) })
+         .collect()
+}
+
+fn usage(command: &str) -> String {
+    let line = HELP.lines().find(|line| line.trim_start().starts_with(command)).unwrap_or(command);
+    format!("usage: {}", line.trim())
+}
+
+fn invalid(kind: ErrorKind, message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(KeynoteError::new(kind, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn lines(session: &mut Session, line: &str) -> Vec<String> {
+        match session.execute(line).unwrap() {
+            Reply::Lines(lines) => lines,
+            Reply::Quit => panic!("{} quit", line)
+        }
+    }
+
+    #[test]
+    fn commands_use_the_current_section() {
+        let mut file = KeynoteFile::new("kntest_repl_commands.dat").unwrap();
+        file.add_section("leaders").unwrap();
+        file.add_section("fremen").unwrap();
+        let filepath = file.filepath.clone();
+        let mut session = Session::new(file);
+
+        assert!(session.execute("add stilgar naib").is_err());
+        lines(&mut session, "use fremen");
+        assert_eq!(session.prompt(), "kn:fremen> ");
+        lines(&mut session, "add stilgar naib of sietch tabr");
+        lines(&mut session, "add leaders atreides leto");
+        assert_eq!(lines(&mut session, "ls"), ["stilgar  naib of sietch tabr"]);
+        assert_eq!(lines(&mut session, "get atreides"), ["atreides:   leto"]);
+        assert_eq!(lines(&mut session, "find TABR"), ["fremen  stilgar"]);
+
+        lines(&mut session, "rm stilgar");
+        lines(&mut session, "use");
+        assert_eq!(lines(&mut session, "ls"), ["fremen", "leaders"]);
+        assert_eq!(session.execute("use harkonnen").map_err(|e| ErrorKind::of(e.as_ref())), Err(ErrorKind::NotFound));
+        assert!(session.execute("get").unwrap_err().to_string().starts_with("usage: get"));
+        assert_eq!(session.execute("exit").unwrap(), Reply::Quit);
+
+        let mut reloaded = KeynoteFile::with_path(&filepath);
+        reloaded.load_data().unwrap();
+        assert_eq!(reloaded.get_value_from_key("atreides"), Some("leto"));
+        assert!(!reloaded.contains_key("stilgar"));
+
+        fs::remove_file(filepath).unwrap();
+    }
+
+    #[test]
+    fn completes_commands_keys_and_sections() {
+        let completer = NameCompleter { keys: vec!["atreides".to_string(), "harkonnen".to_string()],
+                                        sections: vec!["leaders".to_string()] };
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let replacements = |line: &str| -> Vec<String> {
+            completer.complete(line, line.len(), &ctx).unwrap().1.into_iter().map(|pair| pair.replacement).collect()
+        };
+
+        assert_eq!(replacements("g"), ["get "]);
+        assert_eq!(replacements("get a"), ["atreides "]);
+        assert_eq!(replacements("use l"), ["leaders "]);
+        assert!(replacements("get atreides x").is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{KeynoteFile, is_secret_value};
+
+impl KeynoteFile {
+    /// Returns the section and key of each entry whose key or value holds some text, ignoring case,
+    /// sorted by section then key. Secret values are never searched
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - text to search for
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_search_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+    ///
+    /// assert_eq!(kn_file.search("LETO"), vec![("leaders", "atreides")]);
+    /// assert_eq!(kn_file.search("kon"), vec![("leaders", "harkonnen")]);
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn search(&self, text: &str) -> Vec<(&str, &str)> {
+        let text = text.to_lowercase();
+        let mut matches = Vec::new();
+        for section in self.get_sections().values() {
+            for (key, value) in &section.data {
+                let value_match = !is_secret_value(value) && value.to_lowercase().contains(&text);
+                if key.to_lowercase().contains(&text) || value_match {
+                    matches.push((section.name.as_str(), key.as_str()));
+                }
+            }
+        }
+
+        matches.sort();
+        matches
+    }
+}
@@ -0,0 +1,261 @@
+use std::error::Error;
+
+use crate::{KeynoteFile, Operation, encryption::{Cipher, is_sealed}, error::{corrupt, invalid_input, not_found}, recovery::lock_writes};
+
+/// shown in place of the value of a secret entry
+pub const SECRET_MASK: &str = "****";
+
+// secret values are stored as this prefix followed by the hex encoded encrypted value
+const SECRET_PREFIX: &str = "enc:";
+
+/// Checks if a value, as stored in the data file, is the encrypted value of a secret entry
+///
+/// # Arguments
+///
+/// * `value` - value as held in a Section
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+/// assert!(!is_secret_value("leto"));
+/// assert!(!is_secret_value("enc:not hex"));
+/// ```
+pub fn is_secret_value(value: &str) -> bool {
+    match value.strip_prefix(SECRET_PREFIX).and_then(decode_hex) {
+        Some(bytes) => is_sealed(&bytes),
+        None => false
+    }
+}
+
+/// Returns the value to show for an entry, the mask if it is secret
+///
+/// # Arguments
+///
+/// * `value` - value as held in a Section
+///
+/// # Examples    ///
+/// ```
+/// use keydata::*;
+/// assert_eq!(masked_value("leto"), "leto");
+/// ```
+pub fn masked_value(value: &str) -> &str {
+    if is_secret_value(value) { SECRET_MASK } else { value }
+}
+
+impl KeynoteFile {
+    /// Sets the passphrase that secret entry values are encrypted with. It is separate from the passphrase
+    /// given to `set_passphrase`, so secrets can be kept in a file that is otherwise plain text
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - passphrase to derive the key for secret values from, or None
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_secret_passphrase_doc.dat").unwrap();
+    /// kn_file.set_secret_passphrase(Some("arrakis"));
+    /// kn_file.add_section("logins").unwrap();
+    /// kn_file.add_secret_entry("logins", "spice", "melange").unwrap();
+    ///
+    /// assert_eq!(masked_value(kn_file.get_value_from_key("spice").unwrap()), SECRET_MASK);
+    /// assert_eq!(kn_file.reveal("spice").unwrap(), "melange");
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn set_secret_passphrase(&mut self, passphrase: Option<&str>) {
+        self.secret_cipher = passphrase.map(Cipher::new);
+    }
+
+    /// Adds an entry whose value is encrypted in the data file. Needs a secret passphrase
+    ///
+    /// # Arguments
+    ///
+    /// * `section_to_add_to` - section to add entry to as string slice
+    /// * `key` - key for the entry as string slice
+    /// * `value` - value of the entry, before encryption
+    pub fn add_secret_entry(&mut self, section_to_add_to: &str, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let sealed = self.seal_secret(key, value)?;
+        self.add_entry(section_to_add_to, key, &sealed)
+    }
+
+    /// Checks if an entry is secret
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry
+    pub fn is_secret(&mut self, key: &str) -> bool {
+        self.get_value_from_key(key).map(is_secret_value).unwrap_or(false)
+    }
+
+    /// Makes an existing entry secret, encrypting its value, or makes a secret entry plain again.
+    /// Needs a secret passphrase. The values an entry made secret held before are encrypted too, in its
+    /// history, the journal and the backups
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry
+    /// * `secret` - true to make the entry secret, false to store its value as plain text
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_set_secret_doc.dat").unwrap();
+    /// kn_file.set_secret_passphrase(Some("arrakis"));
+    /// kn_file.add_section("logins").unwrap();
+    /// kn_file.add_entry("logins", "spice", "melange").unwrap();
+    ///
+    /// kn_file.set_secret("spice", true).unwrap();
+    /// assert!(kn_file.is_secret("spice"));
+    /// kn_file.set_secret("spice", false).unwrap();
+    /// assert_eq!(kn_file.get_value_from_key("spice"), Some("melange"));
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn set_secret(&mut self, key: &str, secret: bool) -> Result<(), Box<dyn Error>> {
+        if self.is_secret(key) == secret {
+            return Ok(());
+        }
+
+        let value = self.reveal(key)?;
+        let stored = if secret { self.seal_secret(key, &value)? } else { value };
+        let keys = self.earlier_keys(key)?;
+
+        // the value is neither versioned nor journaled, an entry made secret is encrypted wherever it was kept
+        let (section, _) = self.replace_entry_value(key, &stored)?;
+        if secret {
+            let _lock = lock_writes(&self.filepath)?;
+            self.seal_versions(&keys)?;
+            self.seal_journaled_values(&keys)?;
+            self.seal_backed_up_values(&keys)?;
+        }
+        self.record_operation(Operation::SetSecret { section, key: key.to_string(), secret })
+    }
+
+    /// Returns the value of an entry, decrypted if it is secret. Needs a secret passphrase for secret entries
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry
+    pub fn reveal(&mut self, key: &str) -> Result<String, Box<dyn Error>> {
+        let value = match self.get_value_from_key(key) {
+            Some(value) => value.to_string(),
+            None => return Err(not_found(format!("key: '{}' does not exist", key)))
+        };
+        if !is_secret_value(&value) {
+            return Ok(value);
+        }
+
+        let cipher = self.secret_cipher(key)?;
+        let sealed = value.strip_prefix(SECRET_PREFIX).and_then(decode_hex).unwrap_or_default();
+        let plaintext = cipher.open(&sealed).map_err(|_| invalid_input(format!("unable to reveal '{}'. the passphrase is wrong or the value is damaged", key)))?;
+        String::from_utf8(plaintext).map_err(|_| corrupt(format!("unable to reveal '{}'. the value is damaged", key)))
+    }
+
+    /// Encrypts a value of a secret entry into the form it is stored in. A value that is already
+    /// encrypted is returned as is, so stored values can be written back unchanged
+    pub(crate) fn seal_secret(&self, key: &str, value: &str) -> Result<String, Box<dyn Error>> {
+        if is_secret_value(value) {
+            return Ok(value.to_string());
+        }
+        let sealed = self.secret_cipher(key)?.seal(value.as_bytes())?;
+        Ok(format!("{}{}", SECRET_PREFIX, encode_hex(&sealed)))
+    }
+
+    fn secret_cipher(&self, key: &str) -> Result<&Cipher, Box<dyn Error>> {
+        match &self.secret_cipher {
+            Some(cipher) => Ok(cipher),
+            None => Err(invalid_input(format!("'{}' is secret. a passphrase is needed", key)))
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// is_multiple_of is newer than the rest of the crate needs
+#[allow(clippy::manual_is_multiple_of)]
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::BackupPolicy;
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(decode_hex(&encode_hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn secret_value_stays_encrypted_through_updates() {
+        let mut kn_file = KeynoteFile::new("kntest_secret_entries.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.set_versioning(true);
+        kn_file.set_secret_passphrase(Some("arrakis"));
+        kn_file.add_section("logins").unwrap();
+        kn_file.add_secret_entry("logins", "spice", "melange").unwrap();
+        kn_file.update_entry("spice", "sapho").unwrap();
+
+        let contents = fs::read_to_string(&kn_file.filepath).unwrap();
+        let history = fs::read_to_string(kn_file.history_filepath()).unwrap();
+        let journal = fs::read_to_string(kn_file.journal_filepath()).unwrap();
+        for text in [&contents, &history, &journal] {
+            assert!(!text.contains("melange") && !text.contains("sapho"));
+        }
+
+        // undo writes back the stored value, which must not be encrypted a second time
+        kn_file.undo(1).unwrap();
+        assert_eq!(kn_file.reveal("spice").unwrap(), "melange");
+
+        let mut reader = KeynoteFile::new("kntest_secret_entries.dat").unwrap();
+        reader.load_data().unwrap();
+        assert!(reader.is_secret("spice"));
+        assert!(reader.reveal("spice").is_err());
+        assert!(reader.update_entry("spice", "water").is_err());
+        reader.set_secret_passphrase(Some("caladan"));
+        assert!(reader.reveal("spice").is_err());
+
+        // a plain entry made secret leaves none of the values it held behind, under this key or an earlier one
+        kn_file.set_backup_policy(Some(BackupPolicy::default()));
+        kn_file.add_entry("logins", "water", "caladan").unwrap();
+        kn_file.update_entry("water", "giedi").unwrap();
+        kn_file.rename_entry("water", "thumper").unwrap();
+        kn_file.update_entry("thumper", "arrakeen").unwrap();
+        kn_file.set_secret("thumper", true).unwrap();
+
+        let mut texts = vec![fs::read_to_string(&kn_file.filepath).unwrap(), fs::read_to_string(kn_file.history_filepath()).unwrap(),
+                             fs::read_to_string(kn_file.journal_filepath()).unwrap()];
+        texts.extend(kn_file.list_backups().unwrap().iter().map(|backup| fs::read_to_string(&backup.path).unwrap()));
+        for text in &texts {
+            assert!(!text.contains("caladan") && !text.contains("giedi") && !text.contains("arrakeen"));
+        }
+        // the sealed versions and records still reveal and undo as before
+        assert_eq!(kn_file.history("water").unwrap().len(), 1);
+        kn_file.undo(1).unwrap();
+        assert_eq!(kn_file.get_value_from_key("thumper"), Some("arrakeen"));
+        kn_file.undo(2).unwrap();
+        assert!(kn_file.is_secret("water"));
+        assert_eq!(kn_file.reveal("water").unwrap(), "giedi");
+
+        for backup in kn_file.list_backups().unwrap() {
+            fs::remove_file(backup.path).unwrap();
+        }
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.history_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
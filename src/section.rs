@@ -1,10 +1,21 @@
 use std::collections::HashMap;
+
+use crate::Metadata;
+
+/// separates the parts of a nested section's path, e.g. `work/clientA/servers`
+pub const SECTION_SEPARATOR: char = '/';
+
 /// A Section to hold keynote file entries (key-value pairs)
+#[derive(Debug, Clone)]
 pub struct Section {
     /// name of the Section
     pub name : String,
     /// hashmap to hold key value pairs that make up entries
-    pub data : HashMap<String, String>
+    pub data : HashMap<String, String>,
+    /// created and modified times of the section
+    pub metadata : Metadata,
+    /// created and modified times of entries, by key. entries written by older versions have none
+    pub entry_metadata : HashMap<String, Metadata>
 }
 
 impl Section {
@@ -24,7 +35,46 @@ impl Section {
     pub fn new(name : &str) -> Section {
         Section {
             name: name.to_string(),
-            data : HashMap::new()
+            data : HashMap::new(),
+            metadata : Metadata::default(),
+            entry_metadata : HashMap::new()
+        }
+    }
+
+    /// Returns the path of the section a nested section sits in, or None for a top level section
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - section path as string slice
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Section;
+    /// assert_eq!(Section::parent_path("work/clientA/servers"), Some("work/clientA"));
+    /// assert_eq!(Section::parent_path("work"), None);
+    /// ```
+    pub fn parent_path(path: &str) -> Option<&str> {
+        path.rsplit_once(SECTION_SEPARATOR).map(|(parent, _)| parent)
+    }
+
+    /// Checks if a section path is the same as, or nested anywhere below, another
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - section path to check
+    /// * `ancestor` - section path it may be nested in
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use keydata::Section;
+    /// assert!(Section::is_within("work/clientA", "work"));
+    /// assert!(Section::is_within("work", "work"));
+    /// assert!(!Section::is_within("workshop", "work"));
+    /// ```
+    pub fn is_within(path: &str, ancestor: &str) -> bool {
+        match path.strip_prefix(ancestor) {
+            Some(rest) => rest.is_empty() || rest.starts_with(SECTION_SEPARATOR),
+            None => false
         }
     }
 
@@ -97,6 +147,7 @@ impl Section {
 }
 
 #[cfg(test)]
+#[allow(clippy::len_zero)]
 mod tests {            
     use super::*;
 
@@ -142,6 +193,12 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn parent_path_of_nested_section() {
+        assert_eq!(Section::parent_path("a/b/c"), Some("a/b"));
+        assert_eq!(Section::parent_path("a/b").and_then(Section::parent_path), None);
+    }
+
     #[test]
     fn add_entry_success() {
         let mut section = Section::new("test_section");
@@ -0,0 +1,241 @@
+use std::error::Error;
+
+use crate::{KeynoteFile, Metadata, Operation, error::{corrupt, invalid_input, not_found}};
+
+/// How the tags passed to `entries_with_tags` are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatch {
+    /// entries must carry every tag
+    All,
+    /// entries must carry at least one of the tags
+    Any
+}
+
+impl KeynoteFile {
+    /// Gives an entry a tag. Tagging an entry with a tag it already has does nothing
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry to tag
+    /// * `tag` - tag to give, made up of letters, digits, '-' and '_'
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_tag_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    ///
+    /// kn_file.tag("atreides", "arrakis").unwrap();
+    /// assert_eq!(kn_file.entries_with_tags(&["arrakis"], TagMatch::All), vec![("leaders", "atreides")]);
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn tag(&mut self, key: &str, tag: &str) -> Result<(), Box<dyn Error>> {
+        check_tag(tag)?;
+        if self.set_tag(key, tag, true)? {
+            let (section, _) = self.find_entry(key).unwrap_or_default();
+            self.record_operation(Operation::TagEntry { section, key: key.to_string(), tag: tag.to_string() })?;
+        }
+        Ok(())
+    }
+
+    /// Removes a tag from an entry. Removing a tag the entry does not have does nothing
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the entry to untag
+    /// * `tag` - tag to remove
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_untag_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.tag("atreides", "arrakis").unwrap();
+    ///
+    /// kn_file.untag("atreides", "arrakis").unwrap();
+    /// assert!(kn_file.entries_with_tags(&["arrakis"], TagMatch::All).is_empty());
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn untag(&mut self, key: &str, tag: &str) -> Result<(), Box<dyn Error>> {
+        if self.set_tag(key, tag, false)? {
+            let (section, _) = self.find_entry(key).unwrap_or_default();
+            self.record_operation(Operation::UntagEntry { section, key: key.to_string(), tag: tag.to_string() })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the section and key of each entry matching a tag query, sorted by section then key.
+    /// A tag starting with '!' excludes entries carrying it, whatever `mode` is. With no other tags
+    /// every entry not excluded matches
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - tags to match, e.g. `&["work", "!old"]`
+    /// * `mode` - whether entries need all of the tags or any of them
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_entries_with_tags_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+    /// kn_file.tag("atreides", "arrakis").unwrap();
+    /// kn_file.tag("harkonnen", "arrakis").unwrap();
+    /// kn_file.tag("harkonnen", "villain").unwrap();
+    ///
+    /// let heroes = kn_file.entries_with_tags(&["arrakis", "!villain"], TagMatch::All);
+    /// assert_eq!(heroes, vec![("leaders", "atreides")]);
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn entries_with_tags(&self, tags: &[&str], mode: TagMatch) -> Vec<(&str, &str)> {
+        let (excluded, wanted): (Vec<&str>, Vec<&str>) = tags.iter().partition(|tag| tag.starts_with('!'));
+        let excluded: Vec<&str> = excluded.iter().map(|tag| &tag[1..]).collect();
+
+        let mut matches = Vec::new();
+        for section in self.get_sections().values() {
+            for key in section.data.keys() {
+                let entry_tags = section.entry_metadata.get(key).map(|m| m.tags.as_slice()).unwrap_or_default();
+                let has = |tag: &&str| entry_tags.iter().any(|t| t == tag);
+
+                let wanted_match = wanted.is_empty() || match mode {
+                    TagMatch::All => wanted.iter().all(has),
+                    TagMatch::Any => wanted.iter().any(has)
+                };
+                if wanted_match && !excluded.iter().any(has) {
+                    matches.push((section.name.as_str(), key.as_str()));
+                }
+            }
+        }
+
+        matches.sort();
+        matches
+    }
+
+    /// Returns every tag in use with the number of entries carrying it, sorted by tag
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::fs;
+    /// use keydata::*;
+    ///
+    /// let mut kn_file = KeynoteFile::new("kntest_all_tags_doc.dat").unwrap();
+    /// kn_file.add_section("leaders").unwrap();
+    /// kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+    /// kn_file.tag("atreides", "arrakis").unwrap();
+    ///
+    /// assert_eq!(kn_file.all_tags(), vec![("arrakis".to_string(), 1)]);
+    ///
+    /// fs::remove_file(kn_file.filepath);  // remove the test file
+    /// ```
+    pub fn all_tags(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for section in self.get_sections().values() {
+            for (key, metadata) in &section.entry_metadata {
+                if !section.data.contains_key(key) {
+                    continue;
+                }
+                for tag in &metadata.tags {
+                    match counts.iter_mut().find(|(t, _)| t == tag) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((tag.clone(), 1))
+                    };
+                }
+            }
+        }
+
+        counts.sort();
+        counts
+    }
+
+    // adds or removes a tag on an entry, in the file and the data structure. returns false if nothing changed
+    fn set_tag(&mut self, key: &str, tag: &str, add: bool) -> Result<bool, Box<dyn Error>> {
+        let section_name = match self.find_entry(key) {
+            Some((section_name, _)) => section_name,
+            None => return Err(not_found(format!("key: '{}' does not exist. nothing tagged.", key)))
+        };
+
+        let mut items = self.read_items()?;
+        let index = match KeynoteFile::find_entry_item(&items, key) {
+            Some(index) => index,
+            None => return Err(corrupt("error: file corrupted"))
+        };
+        let metadata = items[index].meta.get_or_insert_with(Metadata::default);
+        let position = metadata.tags.binary_search_by(|t| t.as_str().cmp(tag));
+        match (position, add) {
+            (Err(i), true) => metadata.tags.insert(i, tag.to_string()),
+            (Ok(i), false) => { metadata.tags.remove(i); },
+            _ => return Ok(false)
+        };
+        metadata.touch();
+        let metadata = metadata.clone();
+
+        self.write_items(&items)?;
+
+        if let Some(section) = self.get_section(&section_name) {
+            section.entry_metadata.insert(key.to_string(), metadata);
+        }
+
+        Ok(true)
+    }
+}
+
+// tags are kept in a comma separated metadata field, so are limited to characters that cannot break it
+fn check_tag(tag: &str) -> Result<(), Box<dyn Error>> {
+    if tag.is_empty() || !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(invalid_input(format!("invalid tag '{}'. tags may only contain letters, digits, '-' and '_'", tag)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn tag_queries_combine_and_persist() {
+        let mut kn_file = KeynoteFile::new("kntest_tag_queries.dat").unwrap();
+        kn_file.set_journaling(true);
+        kn_file.add_section("leaders").unwrap();
+        kn_file.add_entry("leaders", "atreides", "leto").unwrap();
+        kn_file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        kn_file.add_entry("leaders", "corrino", "shaddam").unwrap();
+        kn_file.tag("atreides", "arrakis").unwrap();
+        kn_file.tag("harkonnen", "arrakis").unwrap();
+        kn_file.tag("harkonnen", "villain").unwrap();
+        kn_file.tag("corrino", "villain").unwrap();
+
+        // reload to be sure tags were written to the file
+        kn_file.load_data().unwrap();
+
+        let keys = |matches: Vec<(&str, &str)>| matches.iter().map(|(_, k)| k.to_string()).collect::<Vec<String>>();
+        assert_eq!(keys(kn_file.entries_with_tags(&["arrakis", "villain"], TagMatch::All)), vec!["harkonnen"]);
+        assert_eq!(keys(kn_file.entries_with_tags(&["arrakis", "villain"], TagMatch::Any)), vec!["atreides", "corrino", "harkonnen"]);
+        assert_eq!(keys(kn_file.entries_with_tags(&["villain", "!arrakis"], TagMatch::All)), vec!["corrino"]);
+        assert_eq!(keys(kn_file.entries_with_tags(&["!villain"], TagMatch::Any)), vec!["atreides"]);
+        assert_eq!(kn_file.all_tags(), vec![("arrakis".to_string(), 2), ("villain".to_string(), 2)]);
+
+        kn_file.untag("harkonnen", "villain").unwrap();
+        assert_eq!(keys(kn_file.entries_with_tags(&["villain"], TagMatch::All)), vec!["corrino"]);
+        kn_file.undo(1).unwrap();
+        assert_eq!(keys(kn_file.entries_with_tags(&["villain"], TagMatch::All)), vec!["corrino", "harkonnen"]);
+
+        assert!(kn_file.tag("atreides", "bad,tag").is_err());
+        assert!(kn_file.tag("fremen", "arrakis").is_err());
+
+        fs::remove_file(kn_file.journal_filepath()).unwrap();
+        fs::remove_file(kn_file.filepath).unwrap();
+    }
+}
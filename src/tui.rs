@@ -0,0 +1,514 @@
+//! Full-screen interface of kn. Sections are listed on the left and the entries of the selected
+//! section on the right. Every change goes through the KeynoteFile API, so it is journaled and
+//! backed up as it is from the command line
+
+use std::{error::Error, io::{self, IsTerminal}};
+
+use keydata::{KeynoteFile, masked_value};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Flex, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap}
+};
+
+const HELP: &str = "tab switch  ↑↓ move  / search  a add  e edit  r rename  d delete  q quit";
+
+/// the list keys move through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Sections,
+    Entries
+}
+
+/// a change waiting on input or confirmation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    AddSection,
+    AddKey { section: String },
+    AddValue { section: String, key: String },
+    EditValue { key: String },
+    RenameSection { section: String },
+    RenameEntry { key: String },
+    RemoveSection { section: String },
+    RemoveEntry { key: String }
+}
+
+/// what keys currently do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Browse,
+    Search,
+    Prompt { prompt: String, input: String, action: Action },
+    Confirm { question: String, action: Action }
+}
+
+/// State of the interface, apart from the terminal it is drawn on
+pub struct App {
+    file: KeynoteFile,
+    pub focus: Pane,
+    pub mode: Mode,
+    pub search: String,
+    pub status: String,
+    pub quit: bool,
+    // sections shown, after any search
+    sections: Vec<String>,
+    // keys and shown values of the entries in the selected section, after any search
+    entries: Vec<(String, String)>,
+    section_state: ListState,
+    entry_state: ListState
+}
+
+impl App {
+    /// Creates the interface for a loaded notebook
+    pub fn new(file: KeynoteFile) -> App {
+        let mut app = App {
+            file,
+            focus: Pane::Sections,
+            mode: Mode::Browse,
+            search: String::new(),
+            status: String::from(HELP),
+            quit: false,
+            sections: Vec::new(),
+            entries: Vec::new(),
+            section_state: ListState::default(),
+            entry_state: ListState::default()
+        };
+        app.refresh();
+        app
+    }
+
+    pub fn selected_section(&self) -> Option<&str> {
+        self.section_state.selected().and_then(|i| self.sections.get(i)).map(|name| name.as_str())
+    }
+
+    pub fn selected_key(&self) -> Option<&str> {
+        self.entry_state.selected().and_then(|i| self.entries.get(i)).map(|(key, _)| key.as_str())
+    }
+
+    /// Acts on a key press
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+
+        match self.mode.clone() {
+            Mode::Browse => self.browse_key(key.code),
+            Mode::Search => self.search_key(key.code),
+            Mode::Prompt { prompt, input, action } => self.prompt_key(key.code, prompt, input, action),
+            Mode::Confirm { action, .. } => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => {
+                    self.mode = Mode::Browse;
+                    self.apply(action, "");
+                },
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    self.mode = Mode::Browse;
+                    self.status = String::from("nothing removed");
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn browse_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Esc if !self.search.is_empty() => {
+                self.search.clear();
+                self.refresh();
+            },
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab => self.focus = match self.focus {
+                Pane::Sections => Pane::Entries,
+                Pane::Entries => Pane::Sections
+            },
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Pane::Sections,
+            KeyCode::Right | KeyCode::Char('l') => self.focus = Pane::Entries,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Char('/') => {
+                self.mode = Mode::Search;
+                self.status = String::from("type to search keys, values and sections. enter keeps the results, esc clears them");
+            },
+            KeyCode::Char('a') => self.start_add(),
+            KeyCode::Enter if self.focus == Pane::Sections => self.focus = Pane::Entries,
+            KeyCode::Enter | KeyCode::Char('e') => self.start_edit(),
+            KeyCode::Char('r') => self.start_rename(),
+            KeyCode::Char('d') | KeyCode::Delete => self.start_remove(),
+            _ => {}
+        }
+    }
+
+    fn search_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Enter => {
+                self.mode = Mode::Browse;
+                self.status = String::from(HELP);
+            },
+            KeyCode::Esc => {
+                self.mode = Mode::Browse;
+                self.search.clear();
+                self.status = String::from(HELP);
+            },
+            KeyCode::Backspace => {
+                self.search.pop();
+            },
+            KeyCode::Char(c) => self.search.push(c),
+            _ => return
+        }
+        self.refresh();
+    }
+
+    fn prompt_key(&mut self, code: KeyCode, prompt: String, mut input: String, action: Action) {
+        match code {
+            KeyCode::Enter => {
+                self.mode = Mode::Browse;
+                self.apply(action, input.trim());
+                return;
+            },
+            KeyCode::Esc => {
+                self.mode = Mode::Browse;
+                self.status = String::from("cancelled");
+                return;
+            },
+            KeyCode::Backspace => {
+                input.pop();
+            },
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        self.mode = Mode::Prompt { prompt, input, action };
+    }
+
+    fn start_add(&mut self) {
+        let action = match (self.focus, self.selected_section()) {
+            (Pane::Sections, _) => Action::AddSection,
+            (Pane::Entries, Some(section)) => Action::AddKey { section: section.to_string() },
+            (Pane::Entries, None) => {
+                self.status = String::from("add a section first");
+                return;
+            }
+        };
+        let prompt = match &action {
+            Action::AddSection => String::from("new section"),
+            _ => String::from("new key")
+        };
+        self.mode = Mode::Prompt { prompt, input: String::new(), action };
+    }
+
+    fn start_edit(&mut self) {
+        let key = match self.selected_key() {
+            Some(key) => key.to_string(),
+            None => return
+        };
+        // a secret value is never put on screen, so it is typed afresh
+        let input = match self.file.is_secret(&key) {
+            true => String::new(),
+            false => self.file.get_value_from_key(&key).unwrap_or_default().to_string()
+        };
+        self.mode = Mode::Prompt { prompt: format!("value of {}", key), input, action: Action::EditValue { key } };
+    }
+
+    fn start_rename(&mut self) {
+        let (current, action) = match self.focus {
+            Pane::Sections => match self.selected_section() {
+                Some(section) => (section.to_string(), Action::RenameSection { section: section.to_string() }),
+                None => return
+            },
+            Pane::Entries => match self.selected_key() {
+                Some(key) => (key.to_string(), Action::RenameEntry { key: key.to_string() }),
+                None => return
+            }
+        };
+        self.mode = Mode::Prompt { prompt: format!("rename {} to", current), input: current, action };
+    }
+
+    fn start_remove(&mut self) {
+        let (question, action) = match self.focus {
+            Pane::Sections => match self.selected_section() {
+                Some(section) => {
+                    let nested = self.file.subsections(section).len();
+                    let question = match nested {
+                        0 => format!("delete section {} and its entries? (y/n)", section),
+                        n => format!("delete section {}, its entries and {} nested section{}? (y/n)", section, n, if n == 1 { "" } else { "s" })
+                    };
+                    (question, Action::RemoveSection { section: section.to_string() })
+                },
+                None => return
+            },
+            Pane::Entries => match self.selected_key() {
+                Some(key) => (format!("delete entry {}? (y/n)", key), Action::RemoveEntry { key: key.to_string() }),
+                None => return
+            }
+        };
+        self.mode = Mode::Confirm { question, action };
+    }
+
+    // makes a change through the file, reporting the outcome in the status line
+    fn apply(&mut self, action: Action, input: &str) {
+        if input.is_empty() && !matches!(action, Action::RemoveSection { .. } | Action::RemoveEntry { .. }) {
+            self.status = String::from("cancelled, nothing was entered");
+            return;
+        }
+
+        let result: Result<String, Box<dyn Error>> = match &action {
+            Action::AddSection => self.file.add_section(input).map(|_| format!("added section {}", input)),
+            Action::AddKey { section } => match keydata::check_key(input) {
+                Ok(()) if self.file.contains_key(input) => Err(format!("key: {} already exists", input).into()),
+                Ok(()) => {
+                    let action = Action::AddValue { section: section.clone(), key: input.to_string() };
+                    self.mode = Mode::Prompt { prompt: format!("value of {}", input), input: String::new(), action };
+                    return;
+                },
+                Err(e) => Err(e.into())
+            },
+            Action::AddValue { section, key } => self.file.add_entry(section, key, input).map(|_| format!("added {} to {}", key, section)),
+            Action::EditValue { key } => self.file.update_entry(key, input).map(|_| format!("updated {}", key)),
+            Action::RenameSection { section } => self.file.rename_section(section, input).map(|_| format!("renamed {} to {}", section, input)),
+            Action::RenameEntry { key } => self.file.rename_entry(key, input).map(|_| format!("renamed {} to {}", key, input)),
+            Action::RemoveSection { section } => self.file.remove_section(section).map(|_| format!("removed section {}", section)),
+            Action::RemoveEntry { key } => self.file.remove_entry(key).map(|_| format!("removed {}", key))
+        };
+
+        match result {
+            Ok(message) => {
+                self.status = message;
+                self.refresh();
+                self.select_changed(&action, input);
+            },
+            Err(e) => self.status = format!("error: {}", e)
+        }
+    }
+
+    // keeps the changed section or entry selected
+    fn select_changed(&mut self, action: &Action, input: &str) {
+        match action {
+            Action::AddSection | Action::RenameSection { .. } => {
+                if let Some(i) = self.sections.iter().position(|name| name == input) {
+                    self.section_state.select(Some(i));
+                    self.refresh_entries();
+                }
+            },
+            Action::AddValue { key, .. } | Action::EditValue { key } => self.select_key(key),
+            Action::RenameEntry { .. } => self.select_key(input),
+            _ => {}
+        }
+    }
+
+    fn select_key(&mut self, key: &str) {
+        if let Some(i) = self.entries.iter().position(|(k, _)| k == key) {
+            self.entry_state.select(Some(i));
+        }
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        let (state, len) = match self.focus {
+            Pane::Sections => (&mut self.section_state, self.sections.len()),
+            Pane::Entries => (&mut self.entry_state, self.entries.len())
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + step).clamp(0, len as isize - 1) as usize));
+
+        if self.focus == Pane::Sections {
+            self.entry_state.select(Some(0));
+            self.refresh_entries();
+        }
+    }
+
+    // rebuilds the lists from the file, keeping the selection where it can
+    fn refresh(&mut self) {
+        let selected = self.selected_section().map(|name| name.to_string());
+
+        let search = self.search.to_lowercase();
+        let mut sections: Vec<String> = self.file.get_sections().values()
+            .filter(|section| search.is_empty() || section.name.to_lowercase().contains(&search)
+                              || section.data.iter().any(|(k, v)| matches(k, v, &search)))
+            .map(|section| section.name.clone())
+            .collect();
+        sections.sort();
+        self.sections = sections;
+
+        let index = selected.and_then(|name| self.sections.iter().position(|s| *s == name));
+        self.section_state.select(match self.sections.is_empty() {
+            true => None,
+            false => Some(index.unwrap_or(0))
+        });
+        self.refresh_entries();
+    }
+
+    fn refresh_entries(&mut self) {
+        let search = self.search.to_lowercase();
+        let mut entries: Vec<(String, String)> = match self.selected_section().and_then(|name| self.file.get_sections().get(name)) {
+            Some(section) => {
+                let whole_section = search.is_empty() || section.name.to_lowercase().contains(&search);
+                section.data.iter()
+                    .filter(|(k, v)| whole_section || matches(k, v, &search))
+                    .map(|(k, v)| (k.clone(), masked_value(v).to_string()))
+                    .collect()
+            },
+            None => Vec::new()
+        };
+        entries.sort();
+        self.entries = entries;
+
+        let index = self.entry_state.selected().unwrap_or(0);
+        self.entry_state.select(match self.entries.is_empty() {
+            true => None,
+            false => Some(index.min(self.entries.len() - 1))
+        });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] = Layout::vertical([Constraint::Min(3), Constraint::Length(2)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main);
+
+        let focus = self.focus;
+        let title = |text: &str, pane: Pane| {
+            let block = Block::bordered().title(format!(" {} ", text));
+            if focus == pane { block.border_style(Style::new().bold()) } else { block }
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let sections: Vec<ListItem> = self.sections.iter().map(|name| ListItem::new(name.as_str())).collect();
+        let sections = List::new(sections).block(title(&format!("{} ({})", self.file_name(), self.sections.len()), Pane::Sections))
+                                         .highlight_style(highlight);
+        frame.render_stateful_widget(sections, left, &mut self.section_state);
+
+        let width = self.entries.iter().map(|(k, _)| k.chars().count()).max().unwrap_or(0);
+        let entries: Vec<ListItem> = self.entries.iter()
+            .map(|(k, v)| ListItem::new(Line::from(vec![Span::from(format!("{:<width$}  ", k, width = width)).bold(), Span::from(v.as_str())])))
+            .collect();
+        let entries = List::new(entries).block(title(self.selected_section().unwrap_or("entries"), Pane::Entries))
+                                       .highlight_style(highlight);
+        frame.render_stateful_widget(entries, right, &mut self.entry_state);
+
+        let search = match (&self.mode, self.search.is_empty()) {
+            (Mode::Search, _) => format!("/{}▏", self.search),
+            (_, false) => format!("/{}", self.search),
+            (_, true) => String::new()
+        };
+        frame.render_widget(Paragraph::new(vec![Line::from(search), Line::from(self.status.as_str()).dim()]), footer);
+
+        match &self.mode {
+            Mode::Prompt { prompt, input, .. } => dialog(frame, prompt, &format!("{}▏", input), "enter to save, esc to cancel"),
+            Mode::Confirm { question, .. } => dialog(frame, "confirm", question, "y to delete, n to keep"),
+            _ => {}
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.file.filepath.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+    }
+}
+
+/// Runs the interface on a loaded notebook until the user quits
+pub fn run(file: KeynoteFile) -> Result<(), Box<dyn Error>> {
+    if !io::stdout().is_terminal() {
+        return Err(Box::new(keydata::KeynoteError::new(keydata::ErrorKind::InvalidInput, "the terminal interface needs a terminal")));
+    }
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, App::new(file));
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, mut app: App) -> Result<(), Box<dyn Error>> {
+    while !app.quit {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                app.handle_key(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+// an entry matches when its key or value holds the search text. secret values never match
+fn matches(key: &str, value: &str, search: &str) -> bool {
+    key.to_lowercase().contains(search) || (!keydata::is_secret_value(value) && value.to_lowercase().contains(search))
+}
+
+fn dialog(frame: &mut Frame, title: &str, text: &str, hint: &str) {
+    let [area] = Layout::vertical([Constraint::Length(5)]).flex(Flex::Center).areas(frame.area());
+    let [area]: [Rect; 1] = Layout::horizontal([Constraint::Percentage(60)]).flex(Flex::Center).areas(area);
+
+    frame.render_widget(Clear, area);
+    let block = Block::bordered().title(format!(" {} ", title)).title_bottom(Line::from(format!(" {} ", hint)).dim());
+    frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }).block(block), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\x1b' => KeyCode::Esc,
+                '\x08' => KeyCode::Backspace,
+                c => KeyCode::Char(c)
+            };
+            app.handle_key(KeyEvent::from(code));
+        }
+    }
+
+    #[test]
+    fn keys_add_edit_rename_and_delete() {
+        let mut file = KeynoteFile::new("kntest_tui_keys.dat").unwrap();
+        file.add_section("leaders").unwrap();
+        file.add_entry("leaders", "harkonnen", "vladimir").unwrap();
+        let filepath = file.filepath.clone();
+        let mut app = App::new(file);
+
+        press(&mut app, "afremen\n");
+        assert_eq!(app.sections, ["fremen", "leaders"]);
+        assert_eq!(app.selected_section(), Some("fremen"));
+
+        press(&mut app, "\tastilgar\nnaib\n");
+        assert_eq!(app.entries, [("stilgar".to_string(), "naib".to_string())]);
+        press(&mut app, "e\x08\x08\x08\x08sietch\n");
+        assert_eq!(app.entries[0].1, "sietch");
+        press(&mut app, "r\x08\x08\x08\x08\x08\x08\x08chani\n");
+        assert_eq!(app.selected_key(), Some("chani"));
+
+        // nothing is removed until the deletion is confirmed
+        press(&mut app, "dn");
+        assert_eq!(app.entries.len(), 1);
+        press(&mut app, "dy");
+        assert!(app.entries.is_empty());
+
+        press(&mut app, "/vlad");
+        assert_eq!(app.sections, ["leaders"]);
+        press(&mut app, "\n\x1b");
+        assert_eq!(app.sections.len(), 2);
+
+        press(&mut app, "haleaders\n");
+        assert!(app.status.starts_with("error: "));
+
+        press(&mut app, "\tr");
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("harkonnen") && screen.contains("rename harkonnen to"));
+        press(&mut app, "\x1b");
+        press(&mut app, "q");
+        assert!(app.quit);
+
+        let mut reloaded = KeynoteFile::with_path(&filepath);
+        reloaded.load_data().unwrap();
+        assert!(reloaded.get_section("fremen").unwrap().data.is_empty());
+
+        fs::remove_file(filepath).unwrap();
+    }
+}
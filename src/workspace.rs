@@ -0,0 +1,198 @@
+//! A workspace runs lookups and searches across every notebook in a folder. Notebooks are found
+//! when the workspace is created, and each is loaded the first time it is needed
+
+use std::{collections::HashMap, error::Error};
+
+use crate::{KeynoteFile, Notebooks, ParseOptions, is_encrypted, masked_value, error::{invalid_input, not_found}};
+
+/// An entry found in a notebook of a workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotebookEntry {
+    /// name of the notebook holding the entry
+    pub notebook: String,
+    /// section holding the entry
+    pub section: String,
+    /// key of the entry
+    pub key: String,
+    /// value of the entry, the mask for secret entries
+    pub value: String
+}
+
+/// The notebooks in a folder, queried together
+pub struct Workspace {
+    notebooks: Notebooks,
+    names: Vec<String>,
+    loaded: HashMap<String, KeynoteFile>,
+    passphrase: Option<String>
+}
+
+impl Workspace {
+    /// Creates a workspace of the notebooks in a folder. None of them are loaded yet
+    ///
+    /// # Arguments
+    ///
+    /// * `notebooks` - the notebooks to query
+    ///
+    /// # Examples    ///
+    /// ```
+    /// use std::{env, fs};
+    /// use keydata::*;
+    ///
+    /// let folder = env::temp_dir().join("kntest_workspace_doc");
+    /// let notebooks = Notebooks::at(&folder);
+    /// let mut work = notebooks.create("work").unwrap();
+    /// work.add_section("leaders").unwrap();
+    /// work.add_entry("leaders", "atreides", "leto").unwrap();
+    /// notebooks.create("home").unwrap();
+    ///
+    /// let mut workspace = Workspace::new(notebooks).unwrap();
+    /// assert_eq!(workspace.names(), ["home", "work"]);
+    ///
+    /// let found = workspace.find_key("atreides").unwrap();
+    /// assert_eq!(found[0].notebook, "work");
+    /// assert_eq!(found[0].value, "leto");
+    ///
+    /// fs::remove_dir_all(folder);  // remove the test folder
+    /// ```
+    pub fn new(notebooks: Notebooks) -> Result<Workspace, Box<dyn Error>> {
+        let names = notebooks.list()?;
+        Ok(Workspace { notebooks, names, loaded: HashMap::new(), passphrase: None })
+    }
+
+    /// Returns the names of the notebooks in the workspace, sorted
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Sets the passphrase used to load encrypted notebooks. Secret values are only ever returned masked
+    ///
+    /// # Arguments
+    ///
+    /// * `passphrase` - passphrase for encrypted notebooks, or None
+    pub fn set_passphrase(&mut self, passphrase: Option<&str>) {
+        self.passphrase = passphrase.map(|p| p.to_string());
+    }
+
+    /// Checks if a notebook has been loaded
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.loaded.contains_key(name)
+    }
+
+    /// Returns a notebook of the workspace, loading it if this is the first time it is needed. Notebooks are
+    /// loaded read-only, so temp files an interrupted write left next to them are kept for the next load
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the notebook
+    pub fn notebook(&mut self, name: &str) -> Result<&KeynoteFile, Box<dyn Error>> {
+        if !self.names.iter().any(|n| n == name) {
+            return Err(not_found(format!("notebook '{}' does not exist", name)));
+        }
+
+        if !self.loaded.contains_key(name) {
+            let mut kn_file = self.notebooks.open(name)?;
+            if is_encrypted(&kn_file.filepath)? {
+                match &self.passphrase {
+                    Some(passphrase) => kn_file.set_passphrase(Some(passphrase)),
+                    None => return Err(invalid_input(format!("notebook '{}' is encrypted. a passphrase is needed", name)))
+                }
+            }
+            kn_file.load_data_read_only(&ParseOptions::lenient())?;
+            self.loaded.insert(name.to_string(), kn_file);
+        }
+
+        Ok(&self.loaded[name])
+    }
+
+    /// Returns the entries with a key, one for each notebook it is found in
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key to look up
+    pub fn find_key(&mut self, key: &str) -> Result<Vec<NotebookEntry>, Box<dyn Error>> {
+        let mut found = Vec::new();
+        for name in self.names.clone() {
+            let kn_file = self.notebook(&name)?;
+            if let Some((section, value)) = kn_file.find_entry(key) {
+                found.push(NotebookEntry { notebook: name, section, key: key.to_string(), value: masked_value(&value).to_string() });
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns the entries, from every notebook, whose key or value holds some text, ignoring case.
+    /// Sorted by notebook, section then key
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - text to search for
+    pub fn search(&mut self, text: &str) -> Result<Vec<NotebookEntry>, Box<dyn Error>> {
+        let mut found = Vec::new();
+        for name in self.names.clone() {
+            let kn_file = self.notebook(&name)?;
+            for (section, key) in kn_file.search(text) {
+                let value = kn_file.get_sections()[section].data[key].as_str();
+                found.push(NotebookEntry {
+                    notebook: name.clone(),
+                    section: section.to_string(),
+                    key: key.to_string(),
+                    value: masked_value(value).to_string()
+                });
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn notebooks_load_when_first_queried() {
+        let folder = env::temp_dir().join("kntest_workspace_lazy");
+        let notebooks = Notebooks::at(&folder);
+        let mut work = notebooks.create("work").unwrap();
+        work.set_secret_passphrase(Some("arrakis"));
+        work.add_section("logins").unwrap();
+        work.add_secret_entry("logins", "spice", "melange").unwrap();
+        work.add_entry("logins", "sietch", "tabr").unwrap();
+        let mut home = notebooks.create("home").unwrap();
+        home.add_section("leaders").unwrap();
+        home.add_entry("leaders", "sietch", "jacurutu").unwrap();
+        let mut vault = notebooks.create("vault").unwrap();
+        vault.add_section("leaders").unwrap();
+        vault.encrypt("caladan").unwrap();
+
+        // a temp file left by an interrupted write is kept for the next write to recover
+        let tmp_filepath = crate::temp_filepath(&home.filepath);
+        fs::write(&tmp_filepath, "<leaders>\n").unwrap();
+
+        let mut workspace = Workspace::new(notebooks).unwrap();
+        assert!(!workspace.is_loaded("home"));
+        workspace.notebook("home").unwrap();
+        assert!(workspace.is_loaded("home") && !workspace.is_loaded("work"));
+
+        // the encrypted notebook cannot be read without the passphrase
+        assert!(workspace.find_key("sietch").is_err());
+        workspace.set_passphrase(Some("caladan"));
+
+        let found = workspace.find_key("sietch").unwrap();
+        let notebooks_found: Vec<&str> = found.iter().map(|e| e.notebook.as_str()).collect();
+        assert_eq!(notebooks_found, vec!["home", "work"]);
+
+        // secret values are masked, and are not searched
+        assert!(workspace.search("melange").unwrap().is_empty());
+        let found = workspace.search("SPICE").unwrap();
+        assert_eq!(found, vec![NotebookEntry { notebook: "work".to_string(), section: "logins".to_string(),
+                                               key: "spice".to_string(), value: crate::SECRET_MASK.to_string() }]);
+        assert!(tmp_filepath.exists());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
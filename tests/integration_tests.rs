@@ -1,3 +1,6 @@
+// the tests compare lengths with 0
+#![allow(clippy::len_zero)]
+
 use keydata::*;
 
 #[test]